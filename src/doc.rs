@@ -0,0 +1,275 @@
+use types::*;
+use errors::*;
+
+use types::Native::*;
+use types::Special::*;
+use errors::LispError::*;
+use self::ArgType::*;
+
+use std::fmt;
+
+//Arity, signature and description of a builtin, shown by help and apropos
+#[derive(Clone, Copy, Debug)]
+pub struct Doc {
+	pub min_args: usize,
+	pub max_args: Option<usize>, //None when it takes any number of arguments
+	pub params: &'static [ArgType], //the last type also applies to any further arguments
+	pub signature: &'static str,
+	pub description: &'static str
+}
+
+impl Doc {
+	fn new(min_args: usize, max_args: Option<usize>, params: &'static [ArgType],
+		   signature: &'static str, description: &'static str) -> Doc {
+		Doc{min_args, max_args, params, signature, description}
+	}
+
+	//Checks the evaluated arguments against the arity and parameter types, so
+	//the builtin itself only has to handle well formed calls
+	pub fn check(&self, args: &[Datum]) -> Result<(), LispError> {
+		if args.len() < self.min_args || self.max_args.is_some_and(|max| args.len() > max) {
+			return Err(INVALID_ARITY(args.len(), self.min_args, self.max_args));
+		}
+		for (i, arg) in args.iter().enumerate() {
+			let ty = match self.params.get(i).or(self.params.last()) {
+				Some(ty)	=> ty,
+				None		=> break
+			};
+			if !ty.matches(arg) {
+				return Err(INVALID_ARGUMENT_TYPE(Box::new(arg.clone()), ty.name()));
+			}
+		}
+		Ok(())
+	}
+
+	//The name the builtin is called by, taken from its signature
+	pub fn name(&self) -> &'static str {
+		self.signature[1..].split([' ', ')']).next().unwrap_or("")
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[allow(non_camel_case_types)]
+pub enum ArgType {
+	ANY, NUMBER, INTEGER, STRING, SYMBOL, CHARACTER,
+	LIST, CONS, VECTOR, HASH_TABLE, STRUCT, FUNCTION
+}
+
+impl ArgType {
+	pub fn matches(&self, arg: &Datum) -> bool {
+		matches!((*self, arg),
+			(ANY, _) |
+			(NUMBER, &Datum::ATOM(Atom::NUMBER(_))) |
+			(INTEGER, &Datum::ATOM(Atom::NUMBER(Number::INTEGER(_)))) |
+			(INTEGER, &Datum::ATOM(Atom::NUMBER(Number::BIGINT(_)))) |
+			(STRING, &Datum::ATOM(Atom::STRING(_))) |
+			(SYMBOL, &Datum::ATOM(Atom::SYMBOL(_))) |
+			(CHARACTER, &Datum::ATOM(Atom::CHAR(_))) |
+			(LIST, &Datum::LIST(_)) |
+			(CONS, &Datum::LIST(List::CONS(..))) |
+			(VECTOR, &Datum::VECTOR(_)) |
+			(HASH_TABLE, &Datum::HASH_TABLE(_)) |
+			(STRUCT, &Datum::STRUCT(_)) |
+			(FUNCTION, &Datum::FUNCTION(_)))
+	}
+
+	//Used in INVALID_ARGUMENT_TYPE messages
+	pub fn name(&self) -> &'static str {
+		match *self {
+			ANY			=> "any",
+			NUMBER		=> "number",
+			INTEGER		=> "integer",
+			STRING		=> "string",
+			SYMBOL		=> "symbol",
+			CHARACTER	=> "character",
+			LIST		=> "list",
+			CONS		=> "cons",
+			VECTOR		=> "vector",
+			HASH_TABLE	=> "hash table",
+			STRUCT		=> "struct",
+			FUNCTION	=> "function"
+		}
+	}
+}
+
+//Reads as "1", "1 to 2" or "1 or more" followed by "argument(s)"
+pub struct Arity(pub usize, pub Option<usize>);
+
+impl fmt::Display for Arity {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let plural = |n: usize| if n == 1 {"argument"} else {"arguments"};
+		match *self {
+			Arity(min, Some(max)) if min == max	=> write!(f, "{} {}", min, plural(min)),
+			Arity(min, Some(max))				=> write!(f, "{} to {} {}", min, max, plural(max)),
+			Arity(min, None)					=> write!(f, "{} or more arguments", min)
+		}
+	}
+}
+
+impl Native {
+	pub fn doc(&self) -> Doc {
+		match *self {
+			ADD					=> Doc::new(0, None, &[NUMBER], "(+ &rest numbers)", "Returns the sum of the numbers, 0 if there are none."),
+			SUB					=> Doc::new(1, None, &[NUMBER], "(- number &rest numbers)", "Subtracts the remaining numbers from the first, or negates a single number."),
+			MUL					=> Doc::new(0, None, &[NUMBER], "(* &rest numbers)", "Returns the product of the numbers, 1 if there are none."),
+			DIV					=> Doc::new(1, None, &[NUMBER], "(/ number &rest numbers)", "Divides the first number by the rest, or returns the reciprocal of a single number."),
+			MOD					=> Doc::new(2, Some(2), &[NUMBER], "(mod a b)", "Returns the remainder of dividing a by b."),
+			POWI				=> Doc::new(2, Some(2), &[NUMBER, INTEGER], "(powi base exponent)", "Raises a number to an integer power."),
+			POWR				=> Doc::new(2, Some(2), &[NUMBER], "(powr base exponent)", "Raises a number to a real power."),
+			GT					=> Doc::new(0, None, &[NUMBER], "(> &rest numbers)", "True if the numbers are strictly decreasing."),
+			GE					=> Doc::new(0, None, &[NUMBER], "(>= &rest numbers)", "True if the numbers are non-increasing."),
+			LT					=> Doc::new(0, None, &[NUMBER], "(< &rest numbers)", "True if the numbers are strictly increasing."),
+			LE					=> Doc::new(0, None, &[NUMBER], "(<= &rest numbers)", "True if the numbers are non-decreasing."),
+			MATH_EQ				=> Doc::new(0, None, &[NUMBER], "(= &rest numbers)", "True if the numbers are all equal."),
+			LIST_FUNC			=> Doc::new(0, None, &[], "(list &rest items)", "Returns a new list of its arguments."),
+			CONS_FUNC			=> Doc::new(2, Some(2), &[], "(cons car cdr)", "Returns a new cons cell."),
+			CAR					=> Doc::new(1, Some(1), &[LIST], "(car list)", "Returns the first element of a list."),
+			CDR					=> Doc::new(1, Some(1), &[LIST], "(cdr list)", "Returns the list without its first element."),
+			SET_CAR				=> Doc::new(2, Some(2), &[CONS, ANY], "(set-car! cons value)", "Replaces the car of a cons cell in place."),
+			SET_CDR				=> Doc::new(2, Some(2), &[CONS, ANY], "(set-cdr! cons value)", "Replaces the cdr of a cons cell in place."),
+			NTH					=> Doc::new(2, Some(2), &[INTEGER, LIST], "(nth n list)", "Returns the nth element of a list, counting from 0."),
+			NTH_CDR				=> Doc::new(2, Some(2), &[INTEGER, LIST], "(nthcdr n list)", "Returns the list after taking cdr n times."),
+			MOST				=> Doc::new(1, Some(1), &[LIST], "(most list)", "Returns the list without its last element."),
+			LOAD				=> Doc::new(1, Some(2), &[STRING, ANY], "(load path &optional mode)", "Reads and evaluates every form in a file."),
+			FLOOR				=> Doc::new(1, Some(1), &[NUMBER], "(floor number)", "Rounds a number down to an integer."),
+			CEIL				=> Doc::new(1, Some(1), &[NUMBER], "(ceil number)", "Rounds a number up to an integer."),
+			TYPE				=> Doc::new(1, Some(1), &[], "(type value)", "Returns a symbol naming the type of the value."),
+			IS_ATOM				=> Doc::new(1, Some(1), &[], "(atom? value)", "True if the value is not a cons."),
+			IS_LIST				=> Doc::new(1, Some(1), &[], "(list? value)", "True if the value is a cons or NIL."),
+			IS_CONS				=> Doc::new(1, Some(1), &[], "(cons? value)", "True if the value is a cons."),
+			IS_SYMBOL			=> Doc::new(1, Some(1), &[], "(symbol? value)", "True if the value is a symbol."),
+			EQUAL				=> Doc::new(2, Some(2), &[], "(equal? a b)", "True if the values are structurally equal."),
+			EQ					=> Doc::new(2, Some(2), &[], "(eq? a b)", "True if the values are the same object."),
+			WRITE_TO_STRING		=> Doc::new(1, Some(1), &[], "(write-to-string value)", "Returns the printed representation of the value."),
+			READ_FROM_STRING	=> Doc::new(1, Some(1), &[STRING], "(read-from-string string)", "Parses the string and returns the form it contains."),
+			STRING_CONCAT		=> Doc::new(0, None, &[STRING], "(string-concat &rest strings)", "Joins the strings together."),
+			PRINT				=> Doc::new(1, Some(1), &[], "(print value)", "Prints the value and returns it."),
+			FORMAT				=> Doc::new(1, None, &[STRING, ANY], "(format control &rest args)", "Returns the control string with each {} replaced by the next argument."),
+			READER_CASE			=> Doc::new(0, Some(1), &[], "(reader-case &optional mode)", "Returns whether the reader upper-cases symbols, setting the mode to UPCASE or PRESERVE if given."),
+			IS_CHAR				=> Doc::new(1, Some(1), &[], "(char? value)", "True if the value is a character."),
+			CHAR_TO_INTEGER		=> Doc::new(1, Some(1), &[CHARACTER], "(char->integer char)", "Returns the code point of a character."),
+			INTEGER_TO_CHAR		=> Doc::new(1, Some(1), &[INTEGER], "(integer->char code)", "Returns the character with the given code point."),
+			CHAR_UPCASE			=> Doc::new(1, Some(1), &[CHARACTER], "(char-upcase char)", "Returns the upper case form of a character."),
+			CHAR_DOWNCASE		=> Doc::new(1, Some(1), &[CHARACTER], "(char-downcase char)", "Returns the lower case form of a character."),
+			IS_ALPHABETIC		=> Doc::new(1, Some(1), &[CHARACTER], "(char-alphabetic? char)", "True if the character is a letter."),
+			IS_NUMERIC			=> Doc::new(1, Some(1), &[CHARACTER], "(char-numeric? char)", "True if the character is a digit."),
+			IS_WHITESPACE		=> Doc::new(1, Some(1), &[CHARACTER], "(char-whitespace? char)", "True if the character is whitespace."),
+			IS_UPPER_CASE		=> Doc::new(1, Some(1), &[CHARACTER], "(char-upper-case? char)", "True if the character is upper case."),
+			IS_LOWER_CASE		=> Doc::new(1, Some(1), &[CHARACTER], "(char-lower-case? char)", "True if the character is lower case."),
+			STRING_LENGTH		=> Doc::new(1, Some(1), &[STRING], "(string-length string)", "Returns the number of characters in the string."),
+			SUBSTRING			=> Doc::new(2, Some(3), &[STRING, INTEGER], "(substring string start &optional end)", "Returns the characters from start up to end, or to the end of the string."),
+			STRING_SPLIT		=> Doc::new(2, Some(2), &[STRING], "(string-split string separator)", "Splits the string on each occurrence of the separator."),
+			STRING_JOIN			=> Doc::new(1, Some(2), &[LIST, STRING], "(string-join strings &optional separator)", "Joins a list of strings, placing the separator between them."),
+			STRING_INDEX		=> Doc::new(2, Some(2), &[STRING], "(string-index string pattern)", "Returns the index of the first occurrence of the pattern, or NIL."),
+			STRING_REPLACE		=> Doc::new(3, Some(3), &[STRING], "(string-replace string from to)", "Replaces every occurrence of one substring with another."),
+			STRING_UPCASE		=> Doc::new(1, Some(1), &[STRING], "(string-upcase string)", "Returns the string in upper case."),
+			STRING_DOWNCASE		=> Doc::new(1, Some(1), &[STRING], "(string-downcase string)", "Returns the string in lower case."),
+			STRING_TRIM			=> Doc::new(1, Some(1), &[STRING], "(string-trim string)", "Removes leading and trailing whitespace."),
+			STRING_TO_NUMBER	=> Doc::new(1, Some(1), &[STRING], "(string->number string)", "Parses a number, returning NIL if the string is not one."),
+			NUMBER_TO_STRING	=> Doc::new(1, Some(1), &[NUMBER], "(number->string number)", "Returns the printed form of a number."),
+			STRING_TO_LIST		=> Doc::new(1, Some(1), &[STRING], "(string->list string)", "Returns the characters of the string as a list."),
+			LIST_TO_STRING		=> Doc::new(1, Some(1), &[LIST], "(list->string chars)", "Builds a string from a list of characters."),
+			STRING_EQ			=> Doc::new(0, None, &[STRING], "(string= &rest strings)", "True if the strings are all equal."),
+			STRING_LT			=> Doc::new(0, None, &[STRING], "(string< &rest strings)", "True if the strings are in strictly increasing order."),
+			STRING_GT			=> Doc::new(0, None, &[STRING], "(string> &rest strings)", "True if the strings are in strictly decreasing order."),
+			STRING_LE			=> Doc::new(0, None, &[STRING], "(string<= &rest strings)", "True if the strings are in non-decreasing order."),
+			STRING_GE			=> Doc::new(0, None, &[STRING], "(string>= &rest strings)", "True if the strings are in non-increasing order."),
+			VECTOR_FUNC			=> Doc::new(0, None, &[], "(vector &rest items)", "Returns a new vector of its arguments."),
+			IS_VECTOR			=> Doc::new(1, Some(1), &[], "(vector? value)", "True if the value is a vector."),
+			MAKE_VECTOR			=> Doc::new(1, Some(2), &[INTEGER, ANY], "(make-vector length &optional fill)", "Returns a vector of the given length, filled with fill or NIL."),
+			VECTOR_REF			=> Doc::new(2, Some(2), &[VECTOR, INTEGER], "(vector-ref vector index)", "Returns the element at the index."),
+			VECTOR_SET			=> Doc::new(3, Some(3), &[VECTOR, INTEGER, ANY], "(vector-set! vector index value)", "Replaces the element at the index."),
+			VECTOR_LENGTH		=> Doc::new(1, Some(1), &[VECTOR], "(vector-length vector)", "Returns the number of elements in the vector."),
+			VECTOR_TO_LIST		=> Doc::new(1, Some(1), &[VECTOR], "(vector->list vector)", "Returns the elements of the vector as a list."),
+			LIST_TO_VECTOR		=> Doc::new(1, Some(1), &[LIST], "(list->vector list)", "Returns the elements of the list as a vector."),
+			VECTOR_MAP			=> Doc::new(2, Some(2), &[FUNCTION, VECTOR], "(vector-map function vector)", "Returns a new vector of the function applied to each element."),
+			MAKE_HASH_TABLE		=> Doc::new(0, Some(0), &[], "(make-hash-table)", "Returns a new empty hash table keyed with equal?."),
+			IS_HASH_TABLE		=> Doc::new(1, Some(1), &[], "(hash-table? value)", "True if the value is a hash table."),
+			HASH_REF			=> Doc::new(2, Some(3), &[HASH_TABLE, ANY], "(hash-ref table key &optional default)", "Returns the value stored under the key, or default if it is missing."),
+			HASH_SET			=> Doc::new(3, Some(3), &[HASH_TABLE, ANY], "(hash-set! table key value)", "Stores the value under the key."),
+			HASH_REMOVE			=> Doc::new(2, Some(2), &[HASH_TABLE, ANY], "(hash-remove! table key)", "Removes the key and its value from the table."),
+			HASH_KEYS			=> Doc::new(1, Some(1), &[HASH_TABLE], "(hash-keys table)", "Returns the keys in insertion order."),
+			HASH_VALUES			=> Doc::new(1, Some(1), &[HASH_TABLE], "(hash-values table)", "Returns the values in insertion order."),
+			HASH_COUNT			=> Doc::new(1, Some(1), &[HASH_TABLE], "(hash-count table)", "Returns the number of entries in the table."),
+			HASH_TO_LIST		=> Doc::new(1, Some(1), &[HASH_TABLE], "(hash->list table)", "Returns the entries as a list of (key . value) pairs."),
+			HASH_FOR_EACH		=> Doc::new(2, Some(2), &[HASH_TABLE, FUNCTION], "(hash-for-each table function)", "Calls the function with each key and value, in insertion order."),
+			MAKE_STRUCT			=> Doc::new(1, None, &[SYMBOL, ANY], "(make-struct name &rest fields-and-values)", "Returns a struct of the named type with the given fields."),
+			IS_STRUCT			=> Doc::new(1, Some(2), &[ANY, SYMBOL], "(struct? value &optional name)", "True if the value is a struct, of the named type if given."),
+			STRUCT_REF			=> Doc::new(2, Some(3), &[STRUCT, SYMBOL], "(struct-ref struct field &optional name)", "Returns the value of a field."),
+			STRUCT_SET			=> Doc::new(3, Some(4), &[STRUCT, SYMBOL, ANY, SYMBOL], "(struct-set! struct field value &optional name)", "Replaces the value of a field."),
+			ERROR				=> Doc::new(1, None, &[], "(error datum &rest args)", "Raises an error built from a condition, a condition type or a format string."),
+			SIGNAL_FUNC			=> Doc::new(1, None, &[], "(signal datum &rest args)", "Raises a condition if a handler would catch it, otherwise returns NIL."),
+			NOT					=> Doc::new(1, Some(1), &[], "(not value)", "True if the value is NIL."),
+			SET					=> Doc::new(2, Some(2), &[SYMBOL, ANY], "(set symbol value)", "Assigns the value to the symbol it is given."),
+			GENSYM				=> Doc::new(0, Some(0), &[], "(gensym)", "Returns a fresh symbol."),
+			APPLY				=> Doc::new(2, Some(2), &[FUNCTION, LIST], "(apply function args)", "Calls the function with a list of arguments."),
+			EVAL				=> Doc::new(1, Some(1), &[], "(eval form)", "Evaluates the form."),
+			DESCRIBE			=> Doc::new(1, Some(1), &[], "(describe object)", "Prints what the object is, with the lambda list and documentation of functions."),
+			DOCUMENTATION		=> Doc::new(1, Some(1), &[], "(documentation object)", "Returns the documentation string of a function, or NIL."),
+			HELP				=> Doc::new(0, Some(1), &[], "(help &optional object)", "Prints the documentation of a function, or how to use help."),
+			APROPOS				=> Doc::new(1, Some(1), &[], "(apropos string)", "Lists every bound symbol whose name contains the string."),
+			RANDINT				=> Doc::new(1, Some(2), &[INTEGER], "(rand-int n &optional m)", "Returns a random integer from 0 up to n, or from n up to m."),
+			RANDBOOL			=> Doc::new(0, Some(0), &[], "(rand-bool)", "Returns T or NIL at random."),
+			RANDREAL			=> Doc::new(0, Some(2), &[NUMBER], "(rand-real &optional n m)", "Returns a random real from 0 up to 1, from 0 up to n, or from n up to m.")
+		}
+	}
+}
+
+impl Special {
+	pub fn doc(&self) -> Doc {
+		match *self {
+			IF					=> Doc::new(2, Some(3), &[], "(if test then &optional else)", "Evaluates then if the test is true, otherwise else."),
+			COND				=> Doc::new(0, None, &[], "(cond &rest (test &rest body))", "Evaluates the body of the first clause whose test is true."),
+			CASE				=> Doc::new(1, None, &[], "(case key &rest (values &rest body))", "Evaluates the body of the first clause listing the key."),
+			AND					=> Doc::new(0, None, &[], "(and &rest forms)", "Evaluates the forms until one is NIL, returning the last value."),
+			OR					=> Doc::new(0, None, &[], "(or &rest forms)", "Evaluates the forms until one is true, returning its value."),
+			WHEN				=> Doc::new(1, None, &[], "(when test &rest body)", "Evaluates the body if the test is true."),
+			UNLESS				=> Doc::new(1, None, &[], "(unless test &rest body)", "Evaluates the body if the test is NIL."),
+			LET					=> Doc::new(2, Some(2), &[], "(let ((var value) ...) body)", "Evaluates the body with the variables bound in parallel."),
+			LET_STAR			=> Doc::new(2, Some(2), &[], "(let* ((var value) ...) body)", "Evaluates the body with the variables bound in sequence."),
+			PROGN				=> Doc::new(0, None, &[], "(progn &rest forms)", "Evaluates the forms in order, returning the last value."),
+			QUOTE				=> Doc::new(1, Some(1), &[], "(quote form)", "Returns the form unevaluated."),
+			BACKQUOTE			=> Doc::new(1, Some(1), &[], "(backquote form)", "Returns the form with unquoted parts evaluated."),
+			DEFINE				=> Doc::new(2, Some(2), &[], "(define symbol value)", "Binds the symbol to the value."),
+			DEFUN				=> Doc::new(3, Some(4), &[], "(defun name args &optional doc body)", "Defines a named function."),
+			DEFMACRO			=> Doc::new(3, Some(4), &[], "(defmacro name args &optional doc body)", "Defines a named macro."),
+			DEFMACRO_HYGIENIC	=> Doc::new(3, Some(4), &[], "(defmacro-hygienic name args &optional doc body)", "Defines a macro whose backquoted bindings are renamed on each expansion."),
+			DEFSTRUCT			=> Doc::new(1, None, &[], "(defstruct name &rest fields)", "Defines a struct type with a constructor, predicate and accessors."),
+			LAMBDA_FUNC			=> Doc::new(2, Some(2), &[], "(lambda args body)", "Returns an anonymous function."),
+			MACRO_FUNC			=> Doc::new(2, Some(2), &[], "(macro args body)", "Returns an anonymous macro."),
+			MACROEXPAND			=> Doc::new(1, Some(1), &[], "(macroexpand form)", "Expands the macro call once."),
+			MACROEXPAND_ALL		=> Doc::new(1, Some(1), &[], "(macroexpand-all form)", "Expands every macro call in the form."),
+			HANDLER_CASE		=> Doc::new(1, None, &[], "(handler-case form &rest (type (var) &rest body))", "Evaluates the form, running the first clause matching any condition it raises."),
+			IGNORE_ERRORS		=> Doc::new(0, None, &[], "(ignore-errors &rest body)", "Evaluates the body, returning NIL if it raises an error."),
+			UNWIND_PROTECT		=> Doc::new(1, None, &[], "(unwind-protect form &rest cleanup)", "Evaluates the form, then the cleanup forms however it exits."),
+			TIME				=> Doc::new(1, Some(1), &[], "(time form)", "Evaluates the form and prints how long it took.")
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use Interpreter;
+	use testing::*;
+
+	#[test]
+	fn builtins_check_arity_and_types() {
+		assert_eq!(CAR.doc().check(&[int(1), int(2)]), Err(INVALID_ARITY(2, 1, Some(1))));
+		assert_eq!(SUBSTRING.doc().check(&[int(1)]), Err(INVALID_ARITY(1, 2, Some(3))));
+		assert_eq!(SUB.doc().check(&[]), Err(INVALID_ARITY(0, 1, None)));
+		assert_eq!(ADD.doc().check(&[int(1), sym("A")]), Err(INVALID_ARGUMENT_TYPE(Box::new(sym("A")), "number")));
+		assert_eq!(POWI.doc().check(&[int(2), int(3)]), Ok(()));
+		assert_eq!(ADD.doc().name(), "+");
+	}
+
+	#[test]
+	fn arity_messages_name_the_accepted_range() {
+		let mut lisp = Interpreter::new();
+		let message = |lisp: &mut Interpreter, source: &str| lisp.eval_str(source).unwrap_err().message();
+		assert!(message(&mut lisp, "(car 1 2)").ends_with("2 provided but 1 argument expected"));
+		assert!(message(&mut lisp, "(substring \"a\")").ends_with("1 provided but 2 to 3 arguments expected"));
+		assert!(message(&mut lisp, "(-)").ends_with("0 provided but 1 or more arguments expected"));
+		assert!(message(&mut lisp, "(string-length 5)").ends_with("5 should be of type string"));
+	}
+}
@@ -8,123 +8,357 @@ use types::Native::*;
 use types::Datum::*;
 
 use std::collections::HashMap;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::cmp;
+use std::mem;
+
+//builtins, the standard library and the user's top level definitions
+const GLOBAL_FRAMES: usize = 3;
+//builtins and the standard library, whose names are all upper case
+const LIBRARY_FRAMES: usize = 2;
 
 pub struct Env {
-	env_stack: Vec<HashMap<String,Datum>>
+	env_stack: Vec<Frame>,
+	gensym_count: usize,
+	preserve_case: bool,
+	handlers: Vec<Vec<Symbol>>, //condition types caught by each active handler-case
+	calls: Vec<Call>
 }
 
 impl Env {
 	pub fn new() -> Env {
-		let mut map: HashMap<String,Datum> = HashMap::new();
-		map.insert("+".to_string(), FUNCTION(NATIVE(ADD)));
-		map.insert("-".to_string(), FUNCTION(NATIVE(SUB)));
-		map.insert("*".to_string(), FUNCTION(NATIVE(MUL)));
-		map.insert("/".to_string(), FUNCTION(NATIVE(DIV)));
-		map.insert("MOD".to_string(), FUNCTION(NATIVE(MOD)));
-		map.insert("POWI".to_string(), FUNCTION(NATIVE(POWI)));
-		map.insert("POWR".to_string(), FUNCTION(NATIVE(POWR)));
-
-		map.insert(">".to_string(), FUNCTION(NATIVE(GT)));
-		map.insert(">=".to_string(), FUNCTION(NATIVE(GE)));
-		map.insert("<".to_string(), FUNCTION(NATIVE(LT)));
-		map.insert("<=".to_string(), FUNCTION(NATIVE(LE)));
-		map.insert("=".to_string(), FUNCTION(NATIVE(MATH_EQ)));		
-
-		map.insert("LIST".to_string(), FUNCTION(NATIVE(LIST_FUNC)));
-		map.insert("CONS".to_string(), FUNCTION(NATIVE(CONS_FUNC)));
-		map.insert("CAR".to_string(), FUNCTION(NATIVE(CAR)));
-		map.insert("CDR".to_string(), FUNCTION(NATIVE(CDR)));
-
-		map.insert("NTHCDR".to_string(), FUNCTION(NATIVE(NTH_CDR)));
-		map.insert("NTH".to_string(), FUNCTION(NATIVE(NTH)));
-		map.insert("MOST".to_string(), FUNCTION(NATIVE(MOST)));
-
-		map.insert("LOAD".to_string(), FUNCTION(NATIVE(LOAD)));
-
-		map.insert("FLOOR".to_string(), FUNCTION(NATIVE(FLOOR)));
-		map.insert("CEIL".to_string(), FUNCTION(NATIVE(CEIL)));
-
-		map.insert("TYPE".to_string(), FUNCTION(NATIVE(TYPE)));
-
-		map.insert("ATOM?".to_string(), FUNCTION(NATIVE(IS_ATOM)));
-		map.insert("LIST?".to_string(), FUNCTION(NATIVE(IS_LIST)));
-		map.insert("CONS?".to_string(), FUNCTION(NATIVE(IS_CONS)));
-		map.insert("SYMBOL?".to_string(), FUNCTION(NATIVE(IS_SYMBOL)));
-
-		map.insert("EQUAL?".to_string(), FUNCTION(NATIVE(EQUAL)));
-
-		map.insert("WRITE-TO-STRING".to_string(), FUNCTION(NATIVE(WRITE_TO_STRING)));
-		map.insert("READ-FROM-STRING".to_string(), FUNCTION(NATIVE(READ_FROM_STRING)));
-		map.insert("STRING-CONCAT".to_string(), FUNCTION(NATIVE(STRING_CONCAT)));
-		map.insert("PRINT".to_string(), FUNCTION(NATIVE(PRINT)));
-		map.insert("FORMAT".to_string(), FUNCTION(NATIVE(FORMAT)));
-
-		map.insert("NOT".to_string(), FUNCTION(NATIVE(NOT)));
-		map.insert("SET".to_string(), FUNCTION(NATIVE(SET)));
-		map.insert("GENSYM".to_string(), FUNCTION(NATIVE(GENSYM)));
-		map.insert("EVAL".to_string(), FUNCTION(NATIVE(EVAL)));
-		map.insert("APPLY".to_string(), FUNCTION(NATIVE(APPLY)));
-
-		map.insert("RAND-INT".to_string(), FUNCTION(NATIVE(RANDINT)));
-		map.insert("RAND-BOOL".to_string(), FUNCTION(NATIVE(RANDBOOL)));
-		map.insert("RAND-REAL".to_string(), FUNCTION(NATIVE(RANDREAL)));	
-
-		map.insert("IF".to_string(), FUNCTION(SPECIAL(IF)));
-		map.insert("LET".to_string(), FUNCTION(SPECIAL(LET)));
-		map.insert("LET*".to_string(), FUNCTION(SPECIAL(LET_STAR)));
-		map.insert("PROGN".to_string(), FUNCTION(SPECIAL(PROGN)));
-		map.insert("QUOTE".to_string(), FUNCTION(SPECIAL(QUOTE)));
-		map.insert("BACKQUOTE".to_string(), FUNCTION(SPECIAL(BACKQUOTE)));
-		map.insert("DEFINE".to_string(), FUNCTION(SPECIAL(DEFINE)));
-		map.insert("DEFUN".to_string(), FUNCTION(SPECIAL(DEFUN)));
-		map.insert("LAMBDA".to_string(), FUNCTION(SPECIAL(LAMBDA_FUNC)));
-		map.insert("TIME".to_string(), FUNCTION(SPECIAL(TIME)));
-		map.insert("MACRO".to_string(), FUNCTION(SPECIAL(MACRO_FUNC)));
-		map.insert("DEFMACRO".to_string(), FUNCTION(SPECIAL(DEFMACRO)));
-		map.insert("MACROEXPAND".to_string(), FUNCTION(SPECIAL(MACROEXPAND)));
-
-		Env{env_stack: vec![map, HashMap::new()]}
-	}
-
-	pub fn get(&self, key: &String) -> Result<Datum, LispError> {
-		let len = self.env_stack.len();
-		for i in 0..len {
-			if self.env_stack[len-i-1].contains_key(key) {
-				return Ok(self.env_stack[len-i-1][key].clone())
+		let mut map: HashMap<Symbol,Datum> = HashMap::new();
+		map.insert(Symbol::intern("+"), FUNCTION(NATIVE(ADD)));
+		map.insert(Symbol::intern("-"), FUNCTION(NATIVE(SUB)));
+		map.insert(Symbol::intern("*"), FUNCTION(NATIVE(MUL)));
+		map.insert(Symbol::intern("/"), FUNCTION(NATIVE(DIV)));
+		map.insert(Symbol::intern("MOD"), FUNCTION(NATIVE(MOD)));
+		map.insert(Symbol::intern("POWI"), FUNCTION(NATIVE(POWI)));
+		map.insert(Symbol::intern("POWR"), FUNCTION(NATIVE(POWR)));
+
+		map.insert(Symbol::intern(">"), FUNCTION(NATIVE(GT)));
+		map.insert(Symbol::intern(">="), FUNCTION(NATIVE(GE)));
+		map.insert(Symbol::intern("<"), FUNCTION(NATIVE(LT)));
+		map.insert(Symbol::intern("<="), FUNCTION(NATIVE(LE)));
+		map.insert(Symbol::intern("="), FUNCTION(NATIVE(MATH_EQ)));		
+
+		map.insert(Symbol::intern("LIST"), FUNCTION(NATIVE(LIST_FUNC)));
+		map.insert(Symbol::intern("CONS"), FUNCTION(NATIVE(CONS_FUNC)));
+		map.insert(Symbol::intern("CAR"), FUNCTION(NATIVE(CAR)));
+		map.insert(Symbol::intern("CDR"), FUNCTION(NATIVE(CDR)));
+		map.insert(Symbol::intern("SET-CAR!"), FUNCTION(NATIVE(SET_CAR)));
+		map.insert(Symbol::intern("SET-CDR!"), FUNCTION(NATIVE(SET_CDR)));
+
+		map.insert(Symbol::intern("NTHCDR"), FUNCTION(NATIVE(NTH_CDR)));
+		map.insert(Symbol::intern("NTH"), FUNCTION(NATIVE(NTH)));
+		map.insert(Symbol::intern("MOST"), FUNCTION(NATIVE(MOST)));
+
+		map.insert(Symbol::intern("LOAD"), FUNCTION(NATIVE(LOAD)));
+
+		map.insert(Symbol::intern("FLOOR"), FUNCTION(NATIVE(FLOOR)));
+		map.insert(Symbol::intern("CEIL"), FUNCTION(NATIVE(CEIL)));
+
+		map.insert(Symbol::intern("TYPE"), FUNCTION(NATIVE(TYPE)));
+
+		map.insert(Symbol::intern("ATOM?"), FUNCTION(NATIVE(IS_ATOM)));
+		map.insert(Symbol::intern("LIST?"), FUNCTION(NATIVE(IS_LIST)));
+		map.insert(Symbol::intern("CONS?"), FUNCTION(NATIVE(IS_CONS)));
+		map.insert(Symbol::intern("SYMBOL?"), FUNCTION(NATIVE(IS_SYMBOL)));
+
+		map.insert(Symbol::intern("EQUAL?"), FUNCTION(NATIVE(EQUAL)));
+		map.insert(Symbol::intern("EQ?"), FUNCTION(NATIVE(EQ)));
+
+		map.insert(Symbol::intern("WRITE-TO-STRING"), FUNCTION(NATIVE(WRITE_TO_STRING)));
+		map.insert(Symbol::intern("READ-FROM-STRING"), FUNCTION(NATIVE(READ_FROM_STRING)));
+		map.insert(Symbol::intern("STRING-CONCAT"), FUNCTION(NATIVE(STRING_CONCAT)));
+		map.insert(Symbol::intern("PRINT"), FUNCTION(NATIVE(PRINT)));
+		map.insert(Symbol::intern("FORMAT"), FUNCTION(NATIVE(FORMAT)));
+		map.insert(Symbol::intern("READER-CASE"), FUNCTION(NATIVE(READER_CASE)));
+
+		map.insert(Symbol::intern("CHAR?"), FUNCTION(NATIVE(IS_CHAR)));
+		map.insert(Symbol::intern("CHAR->INTEGER"), FUNCTION(NATIVE(CHAR_TO_INTEGER)));
+		map.insert(Symbol::intern("INTEGER->CHAR"), FUNCTION(NATIVE(INTEGER_TO_CHAR)));
+		map.insert(Symbol::intern("CHAR-UPCASE"), FUNCTION(NATIVE(CHAR_UPCASE)));
+		map.insert(Symbol::intern("CHAR-DOWNCASE"), FUNCTION(NATIVE(CHAR_DOWNCASE)));
+		map.insert(Symbol::intern("CHAR-ALPHABETIC?"), FUNCTION(NATIVE(IS_ALPHABETIC)));
+		map.insert(Symbol::intern("CHAR-NUMERIC?"), FUNCTION(NATIVE(IS_NUMERIC)));
+		map.insert(Symbol::intern("CHAR-WHITESPACE?"), FUNCTION(NATIVE(IS_WHITESPACE)));
+		map.insert(Symbol::intern("CHAR-UPPER-CASE?"), FUNCTION(NATIVE(IS_UPPER_CASE)));
+		map.insert(Symbol::intern("CHAR-LOWER-CASE?"), FUNCTION(NATIVE(IS_LOWER_CASE)));
+
+		map.insert(Symbol::intern("STRING-LENGTH"), FUNCTION(NATIVE(STRING_LENGTH)));
+		map.insert(Symbol::intern("SUBSTRING"), FUNCTION(NATIVE(SUBSTRING)));
+		map.insert(Symbol::intern("STRING-SPLIT"), FUNCTION(NATIVE(STRING_SPLIT)));
+		map.insert(Symbol::intern("STRING-JOIN"), FUNCTION(NATIVE(STRING_JOIN)));
+		map.insert(Symbol::intern("STRING-INDEX"), FUNCTION(NATIVE(STRING_INDEX)));
+		map.insert(Symbol::intern("STRING-REPLACE"), FUNCTION(NATIVE(STRING_REPLACE)));
+		map.insert(Symbol::intern("STRING-UPCASE"), FUNCTION(NATIVE(STRING_UPCASE)));
+		map.insert(Symbol::intern("STRING-DOWNCASE"), FUNCTION(NATIVE(STRING_DOWNCASE)));
+		map.insert(Symbol::intern("STRING-TRIM"), FUNCTION(NATIVE(STRING_TRIM)));
+		map.insert(Symbol::intern("STRING->NUMBER"), FUNCTION(NATIVE(STRING_TO_NUMBER)));
+		map.insert(Symbol::intern("NUMBER->STRING"), FUNCTION(NATIVE(NUMBER_TO_STRING)));
+		map.insert(Symbol::intern("STRING->LIST"), FUNCTION(NATIVE(STRING_TO_LIST)));
+		map.insert(Symbol::intern("LIST->STRING"), FUNCTION(NATIVE(LIST_TO_STRING)));
+		map.insert(Symbol::intern("STRING="), FUNCTION(NATIVE(STRING_EQ)));
+		map.insert(Symbol::intern("STRING<"), FUNCTION(NATIVE(STRING_LT)));
+		map.insert(Symbol::intern("STRING>"), FUNCTION(NATIVE(STRING_GT)));
+		map.insert(Symbol::intern("STRING<="), FUNCTION(NATIVE(STRING_LE)));
+		map.insert(Symbol::intern("STRING>="), FUNCTION(NATIVE(STRING_GE)));
+
+		map.insert(Symbol::intern("VECTOR"), FUNCTION(NATIVE(VECTOR_FUNC)));
+		map.insert(Symbol::intern("VECTOR?"), FUNCTION(NATIVE(IS_VECTOR)));
+		map.insert(Symbol::intern("MAKE-VECTOR"), FUNCTION(NATIVE(MAKE_VECTOR)));
+		map.insert(Symbol::intern("VECTOR-REF"), FUNCTION(NATIVE(VECTOR_REF)));
+		map.insert(Symbol::intern("VECTOR-SET!"), FUNCTION(NATIVE(VECTOR_SET)));
+		map.insert(Symbol::intern("VECTOR-LENGTH"), FUNCTION(NATIVE(VECTOR_LENGTH)));
+		map.insert(Symbol::intern("VECTOR->LIST"), FUNCTION(NATIVE(VECTOR_TO_LIST)));
+		map.insert(Symbol::intern("LIST->VECTOR"), FUNCTION(NATIVE(LIST_TO_VECTOR)));
+		map.insert(Symbol::intern("VECTOR-MAP"), FUNCTION(NATIVE(VECTOR_MAP)));
+
+		map.insert(Symbol::intern("MAKE-HASH-TABLE"), FUNCTION(NATIVE(MAKE_HASH_TABLE)));
+		map.insert(Symbol::intern("HASH-TABLE?"), FUNCTION(NATIVE(IS_HASH_TABLE)));
+		map.insert(Symbol::intern("HASH-REF"), FUNCTION(NATIVE(HASH_REF)));
+		map.insert(Symbol::intern("HASH-SET!"), FUNCTION(NATIVE(HASH_SET)));
+		map.insert(Symbol::intern("HASH-REMOVE!"), FUNCTION(NATIVE(HASH_REMOVE)));
+		map.insert(Symbol::intern("HASH-KEYS"), FUNCTION(NATIVE(HASH_KEYS)));
+		map.insert(Symbol::intern("HASH-VALUES"), FUNCTION(NATIVE(HASH_VALUES)));
+		map.insert(Symbol::intern("HASH-COUNT"), FUNCTION(NATIVE(HASH_COUNT)));
+		map.insert(Symbol::intern("HASH->LIST"), FUNCTION(NATIVE(HASH_TO_LIST)));
+		map.insert(Symbol::intern("HASH-FOR-EACH"), FUNCTION(NATIVE(HASH_FOR_EACH)));
+
+		map.insert(Symbol::intern("MAKE-STRUCT"), FUNCTION(NATIVE(MAKE_STRUCT)));
+		map.insert(Symbol::intern("STRUCT?"), FUNCTION(NATIVE(IS_STRUCT)));
+		map.insert(Symbol::intern("STRUCT-REF"), FUNCTION(NATIVE(STRUCT_REF)));
+		map.insert(Symbol::intern("STRUCT-SET!"), FUNCTION(NATIVE(STRUCT_SET)));
+
+		map.insert(Symbol::intern("ERROR"), FUNCTION(NATIVE(ERROR)));
+		map.insert(Symbol::intern("SIGNAL"), FUNCTION(NATIVE(SIGNAL_FUNC)));
+
+		map.insert(Symbol::intern("NOT"), FUNCTION(NATIVE(NOT)));
+		map.insert(Symbol::intern("SET"), FUNCTION(NATIVE(SET)));
+		map.insert(Symbol::intern("GENSYM"), FUNCTION(NATIVE(GENSYM)));
+		map.insert(Symbol::intern("EVAL"), FUNCTION(NATIVE(EVAL)));
+		map.insert(Symbol::intern("APPLY"), FUNCTION(NATIVE(APPLY)));
+		map.insert(Symbol::intern("DESCRIBE"), FUNCTION(NATIVE(DESCRIBE)));
+		map.insert(Symbol::intern("DOCUMENTATION"), FUNCTION(NATIVE(DOCUMENTATION)));
+		map.insert(Symbol::intern("HELP"), FUNCTION(NATIVE(HELP)));
+		map.insert(Symbol::intern("APROPOS"), FUNCTION(NATIVE(APROPOS)));
+
+		map.insert(Symbol::intern("RAND-INT"), FUNCTION(NATIVE(RANDINT)));
+		map.insert(Symbol::intern("RAND-BOOL"), FUNCTION(NATIVE(RANDBOOL)));
+		map.insert(Symbol::intern("RAND-REAL"), FUNCTION(NATIVE(RANDREAL)));	
+
+		map.insert(Symbol::intern("IF"), FUNCTION(SPECIAL(IF)));
+		map.insert(Symbol::intern("LET"), FUNCTION(SPECIAL(LET)));
+		map.insert(Symbol::intern("LET*"), FUNCTION(SPECIAL(LET_STAR)));
+		map.insert(Symbol::intern("PROGN"), FUNCTION(SPECIAL(PROGN)));
+		map.insert(Symbol::intern("QUOTE"), FUNCTION(SPECIAL(QUOTE)));
+		map.insert(Symbol::intern("BACKQUOTE"), FUNCTION(SPECIAL(BACKQUOTE)));
+		map.insert(Symbol::intern("COND"), FUNCTION(SPECIAL(COND)));
+		map.insert(Symbol::intern("CASE"), FUNCTION(SPECIAL(CASE)));
+		map.insert(Symbol::intern("AND"), FUNCTION(SPECIAL(AND)));
+		map.insert(Symbol::intern("OR"), FUNCTION(SPECIAL(OR)));
+		map.insert(Symbol::intern("WHEN"), FUNCTION(SPECIAL(WHEN)));
+		map.insert(Symbol::intern("UNLESS"), FUNCTION(SPECIAL(UNLESS)));
+		map.insert(Symbol::intern("DEFINE"), FUNCTION(SPECIAL(DEFINE)));
+		map.insert(Symbol::intern("DEFUN"), FUNCTION(SPECIAL(DEFUN)));
+		map.insert(Symbol::intern("DEFSTRUCT"), FUNCTION(SPECIAL(DEFSTRUCT)));
+		map.insert(Symbol::intern("HANDLER-CASE"), FUNCTION(SPECIAL(HANDLER_CASE)));
+		map.insert(Symbol::intern("IGNORE-ERRORS"), FUNCTION(SPECIAL(IGNORE_ERRORS)));
+		map.insert(Symbol::intern("UNWIND-PROTECT"), FUNCTION(SPECIAL(UNWIND_PROTECT)));
+		map.insert(Symbol::intern("LAMBDA"), FUNCTION(SPECIAL(LAMBDA_FUNC)));
+		map.insert(Symbol::intern("TIME"), FUNCTION(SPECIAL(TIME)));
+		map.insert(Symbol::intern("MACRO"), FUNCTION(SPECIAL(MACRO_FUNC)));
+		map.insert(Symbol::intern("DEFMACRO"), FUNCTION(SPECIAL(DEFMACRO)));
+		map.insert(Symbol::intern("DEFMACRO-HYGIENIC"), FUNCTION(SPECIAL(DEFMACRO_HYGIENIC)));
+		map.insert(Symbol::intern("MACROEXPAND"), FUNCTION(SPECIAL(MACROEXPAND)));
+		map.insert(Symbol::intern("MACROEXPAND-ALL"), FUNCTION(SPECIAL(MACROEXPAND_ALL)));
+
+		Env{env_stack: vec![Rc::new(RefCell::new(map)), Frame::default()],
+			gensym_count: 0,
+			preserve_case: false,
+			handlers: vec![],
+			calls: vec![]}
+	}
+
+	//Binds a Rust function alongside the builtins. The name is upper-cased like
+	//the reader does, and the function can't be redefined from Lisp. Builtins
+	//can't be replaced, but a global Lisp definition of the name, e.g. from the
+	//standard library, is removed so that the Rust function is the one called.
+	pub fn register<F>(&mut self, name: &str, func: F) -> Result<(), LispError>
+		where F: Fn(&[Datum], &mut Env) -> Result<Datum, LispError> + 'static {
+		let sym = Symbol::intern(&name.to_uppercase());
+		match self.env_stack[0].borrow().get(&sym) {
+			Some(&FUNCTION(NATIVE(_))) | Some(&FUNCTION(SPECIAL(_)))
+				=> return Err(OVERRIDE_RESERVED(sym.to_string())),
+			_	=> {}
+		}
+		for frame in &self.env_stack[1..self.globals()] {
+			frame.borrow_mut().remove(&sym);
+		}
+		self.env_stack[0].borrow_mut().insert(sym, FUNCTION(FOREIGN(Foreign{name: sym, func: Rc::new(func)})));
+		Ok(())
+	}
+
+	pub fn get(&self, key: &Symbol) -> Result<Datum, LispError> {
+		match self.find(*key) {
+			Some((i, sym))	=> Ok(self.env_stack[i].borrow()[&sym].clone()),
+			None			=> Err(UNBOUND_VARIABLE(key.name().to_string()))
+		}
+	}
+
+	//The frame holding key's innermost binding and the symbol it is bound under.
+	//Symbols read with case preserved fall back to their upper-case binding in
+	//the builtins and the standard library, so those stay reachable, but names
+	//that differ only in case are kept apart everywhere else.
+	fn find(&self, key: Symbol) -> Option<(usize, Symbol)> {
+		for (i, frame) in self.env_stack.iter().enumerate().rev() {
+			if frame.borrow().contains_key(&key) {
+				return Some((i, key));
+			}
+		}
+		let upper = key.upcase();
+		if upper != key {
+			let library = cmp::min(LIBRARY_FRAMES, self.env_stack.len());
+			for i in (0..library).rev() {
+				if self.env_stack[i].borrow().contains_key(&upper) {
+					return Some((i, upper));
+				}
 			}
 		}
-		Err(UNBOUND_VARIABLE(key.clone()))
+		None
 	}
 
-	pub fn set(&mut self, key: String, val: Datum) -> Datum {
-		self.env_stack.last_mut().unwrap().insert(key, val.clone());
+	pub fn set(&mut self, key: Symbol, val: Datum) -> Datum {
+		self.env_stack.last().unwrap().borrow_mut().insert(key, val.clone());
 		val
 	}
 
-	pub fn set_bot(&mut self, key: String, val: Datum) -> Datum {
-		self.env_stack[2].insert(key, val.clone());
+	pub fn set_bot(&mut self, key: Symbol, val: Datum) -> Datum {
+		self.env_stack[2].borrow_mut().insert(key, val.clone());
 		val
 	}
 
+	//Rebinds the binding get would find, so the change is seen by every
+	//closure sharing that frame. Unbound symbols are set globally.
+	pub fn assign(&mut self, key: Symbol, val: Datum) -> Datum {
+		match self.find(key) {
+			Some((i, sym))	=> {
+				self.env_stack[i].borrow_mut().insert(sym, val.clone());
+				val
+			},
+			None			=> self.set_bot(key, val)
+		}
+	}
+
 	pub fn push(&mut self) {
-		self.env_stack.push(HashMap::new());
+		self.env_stack.push(Frame::default());
 	}
 
-	pub fn push_map(&mut self, map: &HashMap<String, Datum>) {
-		self.env_stack.push(map.clone());
+	pub fn push_map(&mut self, map: &HashMap<Symbol, Datum>) {
+		self.env_stack.push(Rc::new(RefCell::new(map.clone())));
 	}
 
 	pub fn pop(&mut self) {
 		self.env_stack.pop();
 	}
 
-	pub fn top(&mut self) -> HashMap<String, Datum> {
-		self.env_stack.last().unwrap().clone()
+	fn globals(&self) -> usize {
+		cmp::min(GLOBAL_FRAMES, self.env_stack.len())
 	}
 
-	pub fn join(&self) -> Vec<(String, Datum)> {
-		self.env_stack.clone().into_iter()
-			.flat_map(|map| map.into_iter())
+	//The frames above the global ones, shared rather than copied so that
+	//closures see later changes to the variables they capture
+	pub fn locals(&self) -> Vec<Frame> {
+		self.env_stack[self.globals()..].to_vec()
+	}
+
+	//Replaces the local frames with a closure's captured scope, returning the
+	//previous stack so it can be restored once the closure returns
+	pub fn enter(&mut self, scope: &[Frame]) -> Vec<Frame> {
+		let mut stack = self.env_stack[..self.globals()].to_vec();
+		stack.extend_from_slice(scope);
+		mem::replace(&mut self.env_stack, stack)
+	}
+
+	pub fn restore(&mut self, stack: Vec<Frame>) {
+		self.env_stack = stack;
+	}
+
+	pub fn preserve_case(&self) -> bool {
+		self.preserve_case
+	}
+
+	pub fn set_preserve_case(&mut self, preserve: bool) {
+		self.preserve_case = preserve;
+	}
+
+	pub fn next_gensym(&mut self) -> usize {
+		self.gensym_count += 1;
+		self.gensym_count - 1
+	}
+
+	pub fn calls(&self) -> &[Call] {
+		&self.calls
+	}
+
+	pub fn push_call(&mut self, call: Call) {
+		self.calls.push(call);
+	}
+
+	pub fn truncate_calls(&mut self, depth: usize) {
+		self.calls.truncate(depth);
+	}
+
+	pub fn push_handlers(&mut self, types: Vec<Symbol>) {
+		self.handlers.push(types);
+	}
+
+	pub fn pop_handlers(&mut self) {
+		self.handlers.pop();
+	}
+
+	//Whether some active handler-case would catch the condition
+	pub fn is_handled(&self, condition: &LispError) -> bool {
+		self.handlers.iter().any(|types| types.iter().any(|&t| condition.handled_by(t)))
+	}
+
+	pub fn join(&self) -> Vec<(Symbol, Datum)> {
+		self.env_stack.iter()
+			.flat_map(|frame| frame.borrow().clone().into_iter())
 			.collect()
 	}
-}
\ No newline at end of file
+}
+impl Default for Env {
+	fn default() -> Env {
+		Env::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use testing::*;
+
+	#[test]
+	fn case_fallback_only_reaches_library_frames() {
+		let mut env = Env::new();
+		env.set(Symbol::intern("*LIBRARY*"), int(1));
+		env.push();
+		env.set(Symbol::intern("USER"), int(2));
+		assert!(env.get(&Symbol::intern("car")).is_ok());
+		assert_eq!(env.get(&Symbol::intern("*library*")), Ok(int(1)));
+		assert!(env.get(&Symbol::intern("user")).is_err());
+	}
+
+	#[test]
+	fn assign_rebinds_what_get_finds() {
+		let mut env = Env::new();
+		env.set(Symbol::intern("*LIBRARY*"), int(1));
+		env.push();
+		env.assign(Symbol::intern("*library*"), int(3));
+		assert_eq!(env.get(&Symbol::intern("*LIBRARY*")), Ok(int(3)));
+		assert!(!env.join().iter().any(|&(sym, _)| sym == "*library*"));
+	}
+}
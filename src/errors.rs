@@ -1,26 +1,48 @@
 use types::*;
+use doc::Arity;
+use types::Datum::*;
+use types::Atom::*;
 
 #[derive(Debug, Clone, PartialEq)]
 #[allow(non_camel_case_types)]
 pub enum LispError {
-	INVALID_ARGUMENT_TYPE(Datum, &'static str),
+	INVALID_ARGUMENT_TYPE(Box<Datum>, &'static str),
 	INVALID_NUMBER_OF_ARGS(usize, usize),
+	INVALID_ARITY(usize, usize, Option<usize>), //provided, then the accepted range
 	UNBOUND_VARIABLE(String),
-	UNKNOWN_FUNCTION(Datum),
-	INVALID_ARG_LIST(Datum),
+	UNKNOWN_FUNCTION(Box<Datum>),
+	INVALID_ARG_LIST(Box<Datum>),
 	OVERRIDE_RESERVED(String),
 	CANNOT_OPEN_FILE(String),
 	INVALID_NUMBER_OF_FORMAT_PARAMS(usize, usize),
-	_NOT_YET_IMPLEMENTED(Datum),
+	_NOT_YET_IMPLEMENTED(Box<Datum>),
 	MULTIPLE_REST_ARGS,
 	MISPLACED_DEFAULT_VALUE,
 	DIVISION_BY_ZERO,
+	ARITHMETIC_OVERFLOW,
+	INDEX_OUT_OF_BOUNDS(Box<Datum>, usize),
+	WRONG_STRUCT_TYPE(Box<Datum>, Symbol),
+	UNKNOWN_FIELD(Box<Datum>, Symbol),
 	MISMATCHED_BRACKETS,
+	UNCLOSED_PARENTHESIS,
+	UNEXPECTED_CLOSE,
+	UNEXPECTED_EOF,
+	UNTERMINATED_STRING,
+	UNTERMINATED_COMMENT,
+	INVALID_ESCAPE(String),
+	LOCATED(Span, Box<LispError>),
+	TRACED(Vec<Call>, Box<LispError>),
+	IN_FUNCTION(Symbol, Box<LispError>),
+	USER_ERROR(Box<Datum>),
+	SIGNAL(Box<Datum>),
 	NO_INPUT
 }
 
 use self::LispError::*;
 
+//Calls shown before the rest of a backtrace is elided
+const MAX_BACKTRACE: usize = 20;
+
 impl LispError {
 	pub fn message(&self) -> String {
 		match self.clone() {
@@ -28,12 +50,14 @@ impl LispError {
 				format!("Invalid argument: {} should be of type {}", act, exp),
 			INVALID_NUMBER_OF_ARGS(act, exp) =>
 				format!("Invalid number of arguments: {} provided but {} expected", act, exp),
+			INVALID_ARITY(act, min, max) =>
+				format!("Invalid number of arguments: {} provided but {} expected", act, Arity(min, max)),
 			UNBOUND_VARIABLE(name) =>
 				format!("Unbound variable: No value set for {}", name),
 			UNKNOWN_FUNCTION(x) =>
 				format!("Unkown function: {} is not a known function or lambda expression", x),
 			INVALID_ARG_LIST(lst) =>
-				format!("Invalid arguments: {} should be a list", lst),
+				format!("Invalid argument list: {}", lst),
 			CANNOT_OPEN_FILE(reason) =>
 				format!("Cannot open file: {}", reason),
 			OVERRIDE_RESERVED(x) =>
@@ -43,15 +67,227 @@ impl LispError {
 			_NOT_YET_IMPLEMENTED(x) =>
 				format!("{} has not been implemented yet", x),
 			MULTIPLE_REST_ARGS =>
-				format!("Error: arg list should contain at most 1 &rest argument"),
+				"Error: arg list should contain at most 1 &rest argument".to_string(),
 			MISPLACED_DEFAULT_VALUE =>
-				format!("Error: arg list only supports default values for &optional and &key arguments"),
+				"Error: arg list only supports default values for &optional and &key arguments".to_string(),
 			DIVISION_BY_ZERO =>
-				format!("Attempted to divide by zero"),
+				"Attempted to divide by zero".to_string(),
+			ARITHMETIC_OVERFLOW =>
+				"Arithmetic overflow: result is too large to be represented".to_string(),
+			INDEX_OUT_OF_BOUNDS(index, len) =>
+				format!("Index out of bounds: {} is not a valid index for a sequence of length {}", index, len),
+			WRONG_STRUCT_TYPE(obj, name) =>
+				format!("Invalid argument: {} should be a {} struct", obj, name),
+			UNKNOWN_FIELD(obj, field) =>
+				format!("Unknown field: {} has no field named {}", obj, field),
 			MISMATCHED_BRACKETS =>
-				format!("Attempted to close a parenthesis with a square bracket or vice versa"),
+				"Attempted to close a parenthesis with a square bracket or vice versa".to_string(),
+			UNCLOSED_PARENTHESIS =>
+				"Unclosed parenthesis: reached end of input before it was closed".to_string(),
+			UNEXPECTED_CLOSE =>
+				"Unexpected closing parenthesis".to_string(),
+			UNEXPECTED_EOF =>
+				"Unexpected end of input".to_string(),
+			UNTERMINATED_STRING =>
+				"Unterminated string: reached end of input before the closing quote".to_string(),
+			UNTERMINATED_COMMENT =>
+				"Unterminated comment: reached end of input before the closing |#".to_string(),
+			INVALID_ESCAPE(seq) =>
+				format!("Invalid escape sequence: \\{}", seq),
+			LOCATED(span, err) =>
+				format!("{}: {}", span, err.message()),
+			IN_FUNCTION(name, err) =>
+				format!("In {}: {}", name, err.message()),
+			TRACED(calls, err) => {
+				let mut msg = format!("{}\nBacktrace, most recent call first:", err.message());
+				for (i, call) in calls.iter().rev().enumerate() {
+					if i == MAX_BACKTRACE && calls.len() > MAX_BACKTRACE + 1 {
+						msg = format!("{}\n  ... {} more", msg, calls.len() - i);
+						break;
+					}
+					msg = format!("{}\n  {}: {}", msg, i, call);
+				}
+				msg
+			},
+			USER_ERROR(condition) =>
+				format!("Error: {}", condition_message(&condition)),
+			SIGNAL(condition) =>
+				format!("Unhandled condition: {}", condition_message(&condition)),
 			NO_INPUT =>
-				format!("No value")
+				"No value".to_string()
+		}
+	}
+
+	//The error without the location and backtrace wrapped around it
+	pub fn root(&self) -> &LispError {
+		match *self {
+			LOCATED(_, ref err) | TRACED(_, ref err) |
+			IN_FUNCTION(_, ref err)						=> err.root(),
+			ref e										=> e
+		}
+	}
+
+	//Attaches the call stack at the point the error was raised. Errors passing
+	//back through outer calls already have one.
+	pub fn traced(self, calls: &[Call]) -> LispError {
+		match self {
+			e @ TRACED(..)	=> e,
+			LOCATED(span, err) => LOCATED(span, Box::new(err.traced(calls))),
+			e if calls.is_empty() => e,
+			e				=> TRACED(calls.to_vec(), Box::new(e))
+		}
+	}
+
+	//Native errors are named after their variant, e.g. DIVISION-BY-ZERO, and
+	//user conditions after the struct they were raised with
+	pub fn condition_type(&self) -> Symbol {
+		let name = match *self {
+			LOCATED(_, ref err) | TRACED(_, ref err) | IN_FUNCTION(_, ref err) => return err.condition_type(),
+			USER_ERROR(ref c) | SIGNAL(ref c)	=> match **c {
+				STRUCT(ref s)	=> return s.borrow().name,
+				_				=> "SIMPLE-ERROR"
+			},
+			INVALID_ARGUMENT_TYPE(..)	=> "INVALID-ARGUMENT-TYPE",
+			INVALID_NUMBER_OF_ARGS(..) |
+			INVALID_ARITY(..)			=> "INVALID-NUMBER-OF-ARGS",
+			UNBOUND_VARIABLE(_)			=> "UNBOUND-VARIABLE",
+			UNKNOWN_FUNCTION(_)			=> "UNKNOWN-FUNCTION",
+			INVALID_ARG_LIST(_)			=> "INVALID-ARG-LIST",
+			OVERRIDE_RESERVED(_)		=> "OVERRIDE-RESERVED",
+			CANNOT_OPEN_FILE(_)			=> "CANNOT-OPEN-FILE",
+			INVALID_NUMBER_OF_FORMAT_PARAMS(..) => "INVALID-NUMBER-OF-FORMAT-PARAMS",
+			_NOT_YET_IMPLEMENTED(_)		=> "NOT-YET-IMPLEMENTED",
+			MULTIPLE_REST_ARGS			=> "MULTIPLE-REST-ARGS",
+			MISPLACED_DEFAULT_VALUE		=> "MISPLACED-DEFAULT-VALUE",
+			DIVISION_BY_ZERO			=> "DIVISION-BY-ZERO",
+			ARITHMETIC_OVERFLOW			=> "ARITHMETIC-OVERFLOW",
+			INDEX_OUT_OF_BOUNDS(..)		=> "INDEX-OUT-OF-BOUNDS",
+			WRONG_STRUCT_TYPE(..)		=> "WRONG-STRUCT-TYPE",
+			UNKNOWN_FIELD(..)			=> "UNKNOWN-FIELD",
+			MISMATCHED_BRACKETS			=> "MISMATCHED-BRACKETS",
+			UNCLOSED_PARENTHESIS		=> "UNCLOSED-PARENTHESIS",
+			UNEXPECTED_CLOSE			=> "UNEXPECTED-CLOSE",
+			UNEXPECTED_EOF				=> "UNEXPECTED-EOF",
+			UNTERMINATED_STRING			=> "UNTERMINATED-STRING",
+			UNTERMINATED_COMMENT		=> "UNTERMINATED-COMMENT",
+			INVALID_ESCAPE(_)			=> "INVALID-ESCAPE",
+			NO_INPUT					=> "NO-INPUT"
+		};
+		Symbol::intern(name)
+	}
+
+	//The object a handler-case clause is given. Native errors become a struct
+	//of their condition type holding the error message.
+	pub fn condition(&self) -> Datum {
+		match *self {
+			LOCATED(_, ref err) | TRACED(_, ref err) | IN_FUNCTION(_, ref err) => err.condition(),
+			USER_ERROR(ref c) | SIGNAL(ref c)		=> (**c).clone(),
+			_										=> Datum::structure(self.condition_type(),
+				vec![(Symbol::intern("MESSAGE"), ATOM(STRING(self.message())))])
+		}
+	}
+
+	//Everything except a condition raised with signal counts as an ERROR
+	pub fn is_error(&self) -> bool {
+		match *self {
+			LOCATED(_, ref err) | TRACED(_, ref err) | IN_FUNCTION(_, ref err) => err.is_error(),
+			SIGNAL(_)			=> false,
+			_					=> true
+		}
+	}
+
+	//Whether a handler-case clause for the given type catches this condition
+	pub fn handled_by(&self, clause: Symbol) -> bool {
+		clause == "CONDITION" || clause == "T" ||
+		(clause == "ERROR" && self.is_error()) ||
+		clause == self.condition_type()
+	}
+}
+
+//The MESSAGE field of a condition struct, or the condition itself
+fn condition_message(condition: &Datum) -> String {
+	if let STRUCT(ref s) = *condition {
+		let s = s.borrow();
+		let message = s.fields.iter().find(|&&(name, _)| name == "MESSAGE");
+		match (s.name == "SIMPLE-ERROR" || s.name == "SIMPLE-CONDITION", message) {
+			(true, Some(&(_, ATOM(STRING(ref m)))))		=> return m.clone(),
+			(false, Some(&(_, ATOM(STRING(ref m)))))	=> return format!("{}: {}", s.name, m),
+			_											=> {}
 		}
 	}
-}
\ No newline at end of file
+	format!("{}", condition)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use Interpreter;
+	use testing::*;
+
+	#[test]
+	fn wrapped_errors_keep_their_condition_type() {
+		let err = IN_FUNCTION(Symbol::intern("/"), Box::new(DIVISION_BY_ZERO));
+		assert_eq!(err.condition_type(), Symbol::intern("DIVISION-BY-ZERO"));
+		assert!(err.handled_by(Symbol::intern("DIVISION-BY-ZERO")));
+		assert!(err.handled_by(Symbol::intern("ERROR")));
+		assert!(!err.handled_by(Symbol::intern("UNBOUND-VARIABLE")));
+	}
+
+	#[test]
+	fn signals_are_conditions_but_not_errors() {
+		let signal = SIGNAL(Box::new(Datum::structure(Symbol::intern("NOTE"), vec![])));
+		assert!(!signal.is_error());
+		assert!(!signal.handled_by(Symbol::intern("ERROR")));
+		assert!(signal.handled_by(Symbol::intern("CONDITION")));
+		assert!(signal.handled_by(Symbol::intern("NOTE")));
+	}
+
+	#[test]
+	fn handler_case_runs_the_matching_clause() {
+		let mut lisp = Interpreter::new();
+		assert_eq!(lisp.eval_str("(handler-case (/ 1 0) (division-by-zero (c) 'oops))"), Ok(sym("OOPS")));
+		assert_eq!(lisp.eval_str("(handler-case (error 'my-error \"x {}\" 1) (my-error (c) (struct-ref c 'message)))"),
+				   Ok(ATOM(STRING("x 1".to_string()))));
+		assert_eq!(lisp.eval_str("(handler-case (handler-case (error \"inner\") (division-by-zero (c) 'wrong)) (error (c) 'outer))"),
+				   Ok(sym("OUTER")));
+		assert_eq!(lisp.eval_str("(handler-case (signal 'note \"hi\") (condition (c) 'caught))"), Ok(sym("CAUGHT")));
+		assert_eq!(lisp.eval_str("(signal 'note \"hi\")"), Ok(LIST(List::NIL)));
+		assert_eq!(lisp.eval_str("(ignore-errors (/ 1 0))"), Ok(LIST(List::NIL)));
+		assert_eq!(lisp.eval_str("(/ 1 0)").map_err(|e| e.condition_type()), Err(Symbol::intern("DIVISION-BY-ZERO")));
+	}
+
+	#[test]
+	fn unwind_protect_cleans_up_after_errors() {
+		let mut lisp = Interpreter::new();
+		assert!(lisp.eval_str("(define n 0)").is_ok());
+		assert_eq!(lisp.eval_str("(ignore-errors (unwind-protect (/ 1 0) (set 'n 1)))"), Ok(LIST(List::NIL)));
+		assert_eq!(lisp.eval_str("n"), Ok(int(1)));
+	}
+	#[test]
+	fn backtraces_list_the_calls_most_recent_first() {
+		let mut lisp = Interpreter::new();
+		run(&mut lisp, "(defun inner (x) (car x))");
+		run(&mut lisp, "(defun outer (y) (+ 1 (inner y)))");
+		let err = lisp.eval_str("(outer 5)").unwrap_err();
+		assert_eq!(err.condition_type(), Symbol::intern("INVALID-ARGUMENT-TYPE"));
+		let message = err.message();
+		let lines: Vec<&str> = message.lines().collect();
+		assert_eq!(lines[1], "Backtrace, most recent call first:");
+		assert!(lines[2].starts_with("  0: (INNER 5) at "));
+		assert!(lines[3].starts_with("  1: (OUTER 5) at "));
+		assert_eq!(lines.len(), 4);
+	}
+
+	#[test]
+	fn long_backtraces_are_elided() {
+		let mut lisp = Interpreter::new();
+		run(&mut lisp, "(defun deep (n) (if (= n 0) (car 1) (+ 1 (deep (- n 1)))))");
+		let message = lisp.eval_str("(deep 30)").unwrap_err().message();
+		let lines: Vec<&str> = message.lines().collect();
+		assert!(lines[2].starts_with("  0: (DEEP 0)"));
+		assert!(lines[21].starts_with("  19: (DEEP 19)"));
+		assert_eq!(lines[22], "  ... 11 more");
+		assert_eq!(lines.len(), 23);
+	}
+}
+
@@ -3,6 +3,7 @@ use errors::*;
 use native::*;
 use types::*;
 use env::*;
+use doc::*;
 
 use errors::LispError::*;
 use types::Function::*;
@@ -17,60 +18,173 @@ use std::fs::File;
 use std::collections::HashMap;
 use time::PreciseTime;
 
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+enum Step {
+	DONE(Datum),
+	TAIL(Datum),
+	PUSHED(Datum),
+	CALL(Lambda, Bindings, Box<Call>)
+}
+
+use self::Step::*;
+
+struct Bindings {
+	params: Vec<Datum>,
+	optn: Vec<Datum>,
+	key: Vec<(Symbol, Datum)>,
+	rest: Vec<Datum>
+}
+
+impl Bindings {
+	//The arguments as they are shown in a backtrace
+	fn values(&self) -> Vec<Datum> {
+		let mut values = self.params.clone();
+		values.extend(self.optn.iter().cloned());
+		for &(key, ref val) in &self.key {
+			values.push(ATOM(SYMBOL(Symbol::intern(&format!(":{}", key)))));
+			values.push(val.clone());
+		}
+		values.extend(self.rest.iter().cloned());
+		values
+	}
+}
+
 pub fn eval(form: &Datum, env: &mut Env) -> Result<Datum, LispError> {
+	run(Ok(TAIL(form.clone())), env)
+}
+
+//Forms in tail position are evaluated by looping here instead of recursing, so
+//tail calls take constant stack space. A call switches to the callee's closure
+//scope, and the caller's stack is restored once the final value is known.
+//Each call is recorded for backtraces, a tail call replacing its caller's entry.
+fn run(start: Result<Step, LispError>, env: &mut Env) -> Result<Datum, LispError> {
+	let depth = env.calls().len();
+	let mut frames: usize = 0;
+	let mut caller: Option<Vec<Frame>> = None;
+	let mut step = start;
+	let res = loop {
+		step = match step {
+			Err(e)				=> break Err(e),
+			Ok(DONE(val))		=> break Ok(val),
+			Ok(TAIL(form))		=> eval_step(&form, env),
+			Ok(PUSHED(form))	=> {
+				frames += 1;
+				eval_step(&form, env)
+			},
+			Ok(CALL(func, binds, call)) => {
+				env.truncate_calls(depth);
+				env.push_call(*call);
+				//Frames pushed by a let in tail position belong to the caller
+				//and must not be saved along with its stack
+				if caller.is_none() {
+					for _ in 0..frames {
+						env.pop();
+					}
+					frames = 0;
+				}
+				let stack = env.enter(&func.env);
+				if caller.is_none() {
+					caller = Some(stack);
+				}
+				match bind_args(&func, binds, env) {
+					Ok(_)	=> eval_step(&func.body, env),
+					Err(e)	=> Err(e)
+				}
+			}
+		}
+	};
+	match caller {
+		Some(stack)	=> env.restore(stack),
+		None		=> for _ in 0..frames {
+			env.pop();
+		}
+	}
+	let res = res.map_err(|e| e.traced(env.calls()));
+	env.truncate_calls(depth);
+	res
+}
+
+fn eval_step(form: &Datum, env: &mut Env) -> Result<Step, LispError> {
 	match *form {
 		LIST(ref l)	=> eval_list(l, env),
-		ATOM(ref a) => eval_atom(a, env),
-		ref e @ _	=> Ok(e.clone())
+		ATOM(ref a) => eval_atom(a, env).map(DONE),
+		ref e		=> Ok(DONE(e.clone()))
 	}
 }
 
-fn eval_list(form: &List, env: &mut Env) -> Result<Datum, LispError> {
+fn eval_list(form: &List, env: &mut Env) -> Result<Step, LispError> {
 	match *form {
 		CONS(ref car, ref cdr) 	=> {
-			let f = eval(car, env);
-			if f.is_err() {
-				return f;
+			let f = eval(&car.borrow().clone(), env);
+			if let Err(e) = f {
+				return Err(e);
 			} match f.ok().unwrap() {
 				FUNCTION(func) 	=> {
-					match *cdr.clone() {
-						LIST(args) 	=> apply(func, args.get_items(), env),
-						_			=> Err(INVALID_ARG_LIST(*cdr.clone()))
+					match cdr.borrow().clone() {
+						LIST(args) 	=> match apply_step(func, args.get_items(), env) {
+							Ok(CALL(func, binds, mut call)) => {
+								if func.name.is_none() {
+									call.name = car.borrow().clone();
+								}
+								call.form = Some(form.clone());
+								Ok(CALL(func, binds, call))
+							},
+							step => step
+						},
+						e			=> Err(INVALID_ARG_LIST(Box::new(e)))
 					}
 				},
-				ref e @ _			=> Err(UNKNOWN_FUNCTION(e.clone()))
+				ref e				=> Err(UNKNOWN_FUNCTION(Box::new(e.clone())))
 			} 
 		},
-		NIL						=> Ok(LIST(NIL))
+		NIL						=> Ok(DONE(LIST(NIL)))
 	}
 }
 
 fn eval_atom(form: &Atom, env: &mut Env) -> Result<Datum, LispError> {
 	match *form {
 		SYMBOL(ref a) 	=> env.get(a),
-		ref e @ _		=> Ok(ATOM(e.clone()))
+		ref e			=> Ok(ATOM(e.clone()))
 	}
 }
 
 fn apply(func: Function, args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
+	run(apply_step(func, args, env), env)
+}
+
+fn apply_step(func: Function, args: Vec<Datum>, env: &mut Env) -> Result<Step, LispError> {
 	match func {
 		SPECIAL(ref s)	=> apply_special(s, args, env),
-		NATIVE(ref n)	=> apply_native(n, args, env),
-		LAMBDA(ref l)	=> apply_lambda(l, args, env),
+		NATIVE(ref n)	=> apply_native(n, args, env).map(DONE),
+		FOREIGN(ref r)	=> apply_foreign(r, args, env).map(DONE),
+		LAMBDA(l)		=> apply_lambda(l, args, env),
 		MACRO(ref m)	=> apply_macro(m, args, env)
 	}
 }
 
-fn apply_native(func: &Native, args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
+fn eval_args(args: &[Datum], env: &mut Env) -> Result<Vec<Datum>, LispError> {
 	let mut items: Vec<Datum> = Vec::with_capacity(args.len());
-	for i in 0..args.len() {
-		let res = eval(&args[i], env);
-		if res.is_err() {
-			return res;
-		} else {
-			items.push(res.ok().unwrap());
+	for arg in args {
+		match eval(arg, env) {
+			Ok(item)	=> items.push(item),
+			Err(e)		=> return Err(e)
 		}
 	}
+	Ok(items)
+}
+
+fn apply_foreign(func: &Foreign, args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
+	eval_args(&args, env).and_then(|items| (func.func)(&items, env))
+}
+
+fn apply_native(func: &Native, args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
+	let items = eval_args(&args, env)?;
+	let doc = func.doc();
+	match doc.check(&items) {
+		Err(e @ INVALID_ARITY(..))	=> return Err(IN_FUNCTION(Symbol::intern(&doc.name().to_uppercase()), Box::new(e))),
+		Err(e)						=> return Err(e),
+		Ok(())						=> {}
+	}
 
 	match *func {
 		ADD					=> add(items),
@@ -81,6 +195,8 @@ fn apply_native(func: &Native, args: Vec<Datum>, env: &mut Env) -> Result<Datum,
 		CONS_FUNC			=> cons(items),
 		CAR 				=> car(items),
 		CDR					=> cdr(items),
+		SET_CAR				=> set_car(items),
+		SET_CDR				=> set_cdr(items),
 		NTH_CDR				=> nth_cdr(items),
 		NTH					=> nth(items),
 		GT 					=> greater_than(items),
@@ -100,71 +216,169 @@ fn apply_native(func: &Native, args: Vec<Datum>, env: &mut Env) -> Result<Datum,
 		IS_CONS				=> is_cons(items),
 		IS_SYMBOL			=> is_symbol(items),
 		EQUAL 				=> equal(items),
+		EQ 					=> eq(items),
 		WRITE_TO_STRING 	=> write_to_string(items),
-		READ_FROM_STRING 	=> read_from_string(items),
+		READ_FROM_STRING 	=> read_from_string(items, env.preserve_case()),
 		STRING_CONCAT		=> string_concat(items),
 		NOT 				=> not(items),
 		PRINT 				=> print(items),
 		SET 				=> set(items, env),
-		GENSYM				=> gensym(items, env),
+		GENSYM				=> gensym(env),
 		APPLY 				=> apply_lisp(items, env),
 		EVAL 				=> eval_lisp(items, env),
+		DESCRIBE			=> describe(items, env),
+		DOCUMENTATION		=> documentation(items, env),
+		HELP				=> help(items, env),
+		APROPOS				=> apropos(items, env),
 		MOST 				=> most(items),
 		RANDINT				=> rand_int(items),
-		RANDBOOL			=> rand_bool(items),
+		RANDBOOL			=> rand_bool(),
 		RANDREAL			=> rand_real(items),
 		FORMAT 				=> format(items),
-		//_					=> Err(_NOT_YET_IMPLEMENTED(FUNCTION(NATIVE(*func))))
+		READER_CASE			=> reader_case(items, env),
+		IS_CHAR				=> is_char(items),
+		CHAR_TO_INTEGER		=> char_to_integer(items),
+		INTEGER_TO_CHAR		=> integer_to_char(items),
+		CHAR_UPCASE			=> char_upcase(items),
+		CHAR_DOWNCASE		=> char_downcase(items),
+		IS_ALPHABETIC		=> char_predicate(items, char::is_alphabetic),
+		IS_NUMERIC			=> char_predicate(items, char::is_numeric),
+		IS_WHITESPACE		=> char_predicate(items, char::is_whitespace),
+		IS_UPPER_CASE		=> char_predicate(items, char::is_uppercase),
+		IS_LOWER_CASE		=> char_predicate(items, char::is_lowercase),
+		STRING_LENGTH		=> string_length(items),
+		SUBSTRING			=> substring(items),
+		STRING_SPLIT		=> string_split(items),
+		STRING_JOIN			=> string_join(items),
+		STRING_INDEX		=> string_index(items),
+		STRING_REPLACE		=> string_replace(items),
+		STRING_UPCASE		=> string_upcase(items),
+		STRING_DOWNCASE		=> string_downcase(items),
+		STRING_TRIM			=> string_trim(items),
+		STRING_TO_NUMBER	=> string_to_number(items),
+		NUMBER_TO_STRING	=> number_to_string(items),
+		STRING_TO_LIST		=> string_to_list(items),
+		LIST_TO_STRING		=> list_to_string(items),
+		STRING_EQ			=> compare_strings(items, |a, b| a == b),
+		STRING_LT			=> compare_strings(items, |a, b| a < b),
+		STRING_GT			=> compare_strings(items, |a, b| a > b),
+		STRING_LE			=> compare_strings(items, |a, b| a <= b),
+		STRING_GE			=> compare_strings(items, |a, b| a >= b),
+		VECTOR_FUNC			=> Ok(Datum::vector(items)),
+		IS_VECTOR			=> is_vector(items),
+		MAKE_VECTOR			=> make_vector(items),
+		VECTOR_REF			=> vector_ref(items),
+		VECTOR_SET			=> vector_set(items),
+		VECTOR_LENGTH		=> vector_length(items),
+		VECTOR_TO_LIST		=> vector_to_list(items),
+		LIST_TO_VECTOR		=> list_to_vector(items),
+		VECTOR_MAP			=> vector_map(items, env),
+		MAKE_HASH_TABLE		=> make_hash_table(),
+		IS_HASH_TABLE		=> is_hash_table(items),
+		HASH_REF			=> hash_ref(items),
+		HASH_SET			=> hash_set(items),
+		HASH_REMOVE			=> hash_remove(items),
+		HASH_KEYS			=> hash_keys(items),
+		HASH_VALUES			=> hash_values(items),
+		HASH_COUNT			=> hash_count(items),
+		HASH_TO_LIST		=> hash_to_list(items),
+		HASH_FOR_EACH		=> hash_for_each(items, env),
+		MAKE_STRUCT			=> make_struct(items),
+		IS_STRUCT			=> is_struct(items),
+		STRUCT_REF			=> struct_ref(items),
+		STRUCT_SET			=> struct_set(items),
+		ERROR				=> make_condition(items, "SIMPLE-ERROR").and_then(|c| Err(USER_ERROR(Box::new(c)))),
+		SIGNAL_FUNC			=> signal(items, env),
+		//_					=> Err(_NOT_YET_IMPLEMENTED(Box::new(FUNCTION(NATIVE(*func)))))
 	}
 }
 
-fn apply_special(func: &Special, args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
+fn apply_special(func: &Special, args: Vec<Datum>, env: &mut Env) -> Result<Step, LispError> {
 	match *func {
-		DEFINE		=> define(args, env),
+		DEFINE		=> define(args, env).map(DONE),
 		IF 			=> lisp_if(args, env),
-		LAMBDA_FUNC => lambda(args, env),
-		DEFUN 		=> defun(args, env),
-		QUOTE 		=> quote(args),
-		BACKQUOTE   => backquote(args, env),
+		COND 		=> cond(args, env),
+		CASE 		=> case(args, env),
+		AND 		=> and(args, env),
+		OR 			=> or(args, env),
+		WHEN 		=> when(args, env),
+		UNLESS 		=> unless(args, env),
+		LAMBDA_FUNC => lambda(args, env).map(DONE),
+		DEFUN 		=> defun(args, env).map(DONE),
+		DEFSTRUCT 	=> defstruct(args, env).map(DONE),
+		HANDLER_CASE => handler_case(args, env),
+		IGNORE_ERRORS => ignore_errors(args, env).map(DONE),
+		UNWIND_PROTECT => unwind_protect(args, env).map(DONE),
+		QUOTE 		=> quote(args).map(DONE),
+		BACKQUOTE   => backquote(args, env).map(DONE),
 		LET 		=> let_lisp(args, env),
 		LET_STAR 	=> let_star(args, env),
 		PROGN 		=> progn(args, env),
-		TIME 		=> time(args, env),
-		MACRO_FUNC  => macro_lisp(args, env),
-		DEFMACRO    => defmacro(args, env),
-		MACROEXPAND => macroexpand(args, env),
-		//_			=> Err(_NOT_YET_IMPLEMENTED(FUNCTION(SPECIAL(*func))))
+		TIME 		=> time(args, env).map(DONE),
+		MACRO_FUNC  => macro_lisp(args, env).map(DONE),
+		DEFMACRO    => defmacro(args, env).map(DONE),
+		DEFMACRO_HYGIENIC => defmacro_hygienic(args, env).map(DONE),
+		MACROEXPAND => macroexpand(args, env).map(DONE),
+		MACROEXPAND_ALL => macroexpand_all_lisp(args, env).map(DONE),
+		//_			=> Err(_NOT_YET_IMPLEMENTED(Box::new(FUNCTION(SPECIAL(*func)))))
+	}
+}
+
+//Evaluates the arguments of a lambda call in the caller's environment. They are
+//bound by bind_args once the closure's scope has been entered.
+fn apply_lambda(func: Lambda, args: Vec<Datum>, env: &mut Env) -> Result<Step, LispError> {
+	let binds = match_args(&func, args, env, true);
+	if let Err(e) = binds {
+		Err(e)
+	} else {
+		let binds = binds.ok().unwrap();
+		let name = ATOM(SYMBOL(func.name.unwrap_or_else(|| Symbol::intern("LAMBDA"))));
+		let call = Box::new(Call{name, args: binds.values(), form: None});
+		Ok(CALL(func, binds, call))
 	}
 }
 
-fn apply_lambda(func: &Lambda, args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
+//Names the function when it has one
+fn arity_error(func: &Lambda, provided: usize) -> LispError {
+	let err = INVALID_NUMBER_OF_ARGS(provided, func.args.len());
+	match func.name {
+		Some(name)	=> IN_FUNCTION(name, Box::new(err)),
+		None		=> err
+	}
+}
+
+//Matches the arguments of a call against a lambda list, evaluating them first
+//unless they are being passed to a macro
+fn match_args(func: &Lambda, args: Vec<Datum>, env: &mut Env, evaluate: bool) -> Result<Bindings, LispError> {
 	if args.len() < func.args.len() {
-		return Err(INVALID_NUMBER_OF_ARGS(args.len(), func.args.len()));
+		return Err(arity_error(func, args.len()));
 	}
 
 	let mut params: Vec<Datum> = Vec::with_capacity(func.args.len());
 	let mut optional_params: Vec<Datum> = Vec::with_capacity(func.optn.len());
-	let mut key_params: Vec<(String, Datum)> = Vec::with_capacity(func.key.len());
+	let mut key_params: Vec<(Symbol, Datum)> = Vec::with_capacity(func.key.len());
 	let mut rest_params: Vec<Datum> = Vec::new();
 
-	for i in 0..func.args.len() {
-		let res = eval(&args[i], env);
-		if res.is_err() {
-			return res;
+	for arg in args.iter().take(func.args.len()) {
+		let res = if evaluate {eval(arg, env)} else {Ok(arg.clone())};
+		if let Err(e) = res {
+			return Err(e);
 		} else {
 			params.push(res.ok().unwrap());
 		}
 	}
 
 	let mut is_key = false;
-	let mut key_name = "";
+	let mut key_name = Symbol::intern("");
 	for i in func.args.len()..args.len() {
-		if let ATOM(SYMBOL(ref name)) = args[i] {
+		if let ATOM(SYMBOL(ref sym)) = args[i] {
+			let name = sym.name();
 			if name.starts_with(':') && i != args.len()-1 {
-				let name = &name[1..];
-				if func.contains_key(name.to_string()) && !is_key {
-					if !key_params.clone().into_iter().any(|(key, _): (String,_)| key==":".to_string()+name) {
-						key_name = &name;
+				let name = Symbol::intern(&name[1..]);
+				if func.contains_key(name) && !is_key {
+					let keyword = *sym;
+					if !key_params.iter().any(|&(key, _)| key == keyword) {
+						key_name = name;
 						is_key = true;
 						continue;
 					}
@@ -172,60 +386,61 @@ fn apply_lambda(func: &Lambda, args: Vec<Datum>, env: &mut Env) -> Result<Datum,
 			} 
 		}
 
-		let res = eval(&args[i], env);
-		if res.is_err() {
-			return res;
+		let res = if evaluate {eval(&args[i], env)} else {Ok(args[i].clone())};
+		if let Err(e) = res {
+			return Err(e);
 		} else if is_key {
-			key_params.push((key_name.to_string(), res.ok().unwrap()));
+			key_params.push((key_name, res.ok().unwrap()));
 			is_key = false;
 		} else if optional_params.len() != func.optn.len() {
 			optional_params.push(res.ok().unwrap());
-		} else if func.rest != None {
+		} else if func.rest.is_some() {
 			rest_params.push(res.ok().unwrap());
 		} else {
-			return Err(INVALID_NUMBER_OF_ARGS(args.len(), func.args.len()));
+			return Err(arity_error(func, args.len()));
 		}
 	}
 
-	env.push_map(&func.env);
-	for (param, arg) in params.into_iter().zip(&func.args) {
-		env.set(arg.clone(), param);
+	Ok(Bindings{params,
+				optn: optional_params,
+				key: key_params,
+				rest: rest_params})
+}
+
+//Pushes a new frame onto the closure's scope and binds the call's parameters
+fn bind_args(func: &Lambda, binds: Bindings, env: &mut Env) -> Result<(), LispError> {
+	env.push();
+	for (param, arg) in binds.params.into_iter().zip(&func.args) {
+		env.set(*arg, param);
 	}
 	for (name, default) in func.optn.clone() {
 		let res = eval(&default, env);
-		if res.is_err() {
-			return res;
+		if let Err(e) = res {
+			return Err(e);
 		}
-		env.set(name.clone(), res.ok().unwrap());
+		env.set(name, res.ok().unwrap());
 	}
-	for (param, arg) in optional_params.into_iter().zip(&func.optn) {
-		env.set(arg.0.clone(), param);
+	for (param, arg) in binds.optn.into_iter().zip(&func.optn) {
+		env.set(arg.0, param);
 	}
 	for (name, default) in func.key.clone() {
 		let res = eval(&default, env);
-		if res.is_err() {
-			return res;
+		if let Err(e) = res {
+			return Err(e);
 		}
-		env.set(name.clone(), res.ok().unwrap());
+		env.set(name, res.ok().unwrap());
 	}
-	for (name, val) in key_params {
+	for (name, val) in binds.key {
 		env.set(name, val);
 	}
-	if let Some(name) = func.rest.clone() {
-		env.set(name.clone(), LIST(List::from_vec(rest_params)));
+	if let Some(name) = func.rest {
+		env.set(name, LIST(List::from_vec(binds.rest)));
 	}
-	let res = eval(&func.body, env);
-	env.pop();
-	res
+	Ok(())
 }
 
-fn apply_macro(func: &Lambda, args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
-	let res = macroexpand_helper(func, args);
-	if res.is_err() {
-		res
-	} else {
-		eval(&res.ok().unwrap(), env)
-	}
+fn apply_macro(func: &Lambda, args: Vec<Datum>, env: &mut Env) -> Result<Step, LispError> {
+	macroexpand_helper(func, args, env).map(TAIL)
 }
 
 fn define(args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
@@ -237,54 +452,157 @@ fn define(args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
 					return res;
 				} else {
 					match env.get(&sym) {
-						Ok(FUNCTION(SPECIAL(_))) | Ok(FUNCTION(NATIVE(_))) 
-							=> return Err(OVERRIDE_RESERVED(sym)),
+						Ok(FUNCTION(SPECIAL(_))) | Ok(FUNCTION(NATIVE(_))) | Ok(FUNCTION(FOREIGN(_)))
+							=> return Err(OVERRIDE_RESERVED(sym.to_string())),
 						_ 	=> return Ok(env.set_bot(sym, res.ok().unwrap()))
 					}
 				}
 			}
-			Err(INVALID_ARGUMENT_TYPE(args[0].clone(), "symbol"))
+			Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "symbol"))
 		},
-		e @ _ => Err(INVALID_NUMBER_OF_ARGS(e, 2))
+		e => Err(INVALID_NUMBER_OF_ARGS(e, 2))
 	}
 }
 
 fn is_true(cond: Datum) -> bool {
-	match cond {
-		LIST(NIL)	=> false,
-		_			=> true
-	}
+	!matches!(cond, LIST(NIL))
 }
 
-fn lisp_if(args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
+fn lisp_if(args: Vec<Datum>, env: &mut Env) -> Result<Step, LispError> {
 	match args.len() {
 		2 | 3 	=> {
 			let res = eval(&args[0], env);
-			if res.is_err() {
-				res
+			if let Err(e) = res {
+				Err(e)
 			} else if is_true(res.ok().unwrap()) {
-				eval(&args[1], env)
+				Ok(TAIL(args[1].clone()))
 			} else if args.len() == 3 {
-				eval(&args[2], env)
+				Ok(TAIL(args[2].clone()))
 			} else {
-				Ok(LIST(NIL))
+				Ok(DONE(LIST(NIL)))
 			}
 		},
-		e @ _	=> Err(INVALID_NUMBER_OF_ARGS(e, 3))
+		e		=> Err(INVALID_NUMBER_OF_ARGS(e, 3))
+	}
+}
+
+//The clause whose test passes has its body evaluated in tail position. A clause
+//without a body returns the value of its test.
+fn cond(args: Vec<Datum>, env: &mut Env) -> Result<Step, LispError> {
+	for clause in args {
+		if let LIST(lst@CONS(..)) = clause {
+			let mut items = lst.get_items();
+			let res = eval(&items[0], env);
+			if let Err(e) = res {
+				return Err(e);
+			}
+			let test = res.ok().unwrap();
+			if is_true(test.clone()) {
+				return if items.len() == 1 {
+					Ok(DONE(test))
+				} else {
+					progn(items.split_off(1), env)
+				};
+			}
+		} else {
+			return Err(INVALID_ARGUMENT_TYPE(Box::new(clause), "nonempty list"));
+		}
 	}
+	Ok(DONE(LIST(NIL)))
 }
 
-fn lambda_contains(sym: String, tree: Datum) -> bool {
-	if ATOM(SYMBOL(sym.clone())) == tree {
-		true
-	} else if let LIST(CONS(ref a, ref b)) = tree {
-		if lambda_contains(sym.clone(), *a.clone()) {
-			true
+//Each clause starts with either a single key, a list of keys, or T/OTHERWISE.
+//Keys are not evaluated and are compared to the value with equal?.
+fn case(args: Vec<Datum>, env: &mut Env) -> Result<Step, LispError> {
+	if args.is_empty() {
+		return Err(INVALID_NUMBER_OF_ARGS(0, 1));
+	}
+
+	let res = eval(&args[0], env);
+	if let Err(e) = res {
+		return Err(e);
+	}
+	let val = res.ok().unwrap();
+	for clause in args.into_iter().skip(1) {
+		if let LIST(lst@CONS(..)) = clause {
+			let mut items = lst.get_items();
+			let matches = match items[0] {
+				ATOM(T)							=> true,
+				ATOM(SYMBOL(ref s)) if s.name().to_uppercase() == "OTHERWISE"	=> true,
+				LIST(ref keys)					=> keys.get_items().iter().any(|key| is_equal(key, &val)),
+				ref key							=> is_equal(key, &val)
+			};
+			if matches {
+				return progn(items.split_off(1), env);
+			}
 		} else {
-			lambda_contains(sym, *b.clone())
+			return Err(INVALID_ARGUMENT_TYPE(Box::new(clause), "nonempty list"));
 		}
+	}
+	Ok(DONE(LIST(NIL)))
+}
+
+fn and(mut args: Vec<Datum>, env: &mut Env) -> Result<Step, LispError> {
+	let last = match args.pop() {
+		Some(form)	=> form,
+		None		=> return Ok(DONE(ATOM(T)))
+	};
+	for item in args {
+		let res = eval(&item, env);
+		if let Err(e) = res {
+			return Err(e);
+		} else if !is_true(res.ok().unwrap()) {
+			return Ok(DONE(LIST(NIL)));
+		}
+	}
+	Ok(TAIL(last))
+}
+
+fn or(mut args: Vec<Datum>, env: &mut Env) -> Result<Step, LispError> {
+	let last = match args.pop() {
+		Some(form)	=> form,
+		None		=> return Ok(DONE(LIST(NIL)))
+	};
+	for item in args {
+		let res = eval(&item, env);
+		if let Err(e) = res {
+			return Err(e);
+		}
+		let val = res.ok().unwrap();
+		if is_true(val.clone()) {
+			return Ok(DONE(val));
+		}
+	}
+	Ok(TAIL(last))
+}
+
+fn when(mut args: Vec<Datum>, env: &mut Env) -> Result<Step, LispError> {
+	if args.is_empty() {
+		return Err(INVALID_NUMBER_OF_ARGS(0, 1));
+	}
+
+	let res = eval(&args[0], env);
+	if let Err(e) = res {
+		Err(e)
+	} else if is_true(res.ok().unwrap()) {
+		progn(args.split_off(1), env)
 	} else {
-		false
+		Ok(DONE(LIST(NIL)))
+	}
+}
+
+fn unless(mut args: Vec<Datum>, env: &mut Env) -> Result<Step, LispError> {
+	if args.is_empty() {
+		return Err(INVALID_NUMBER_OF_ARGS(0, 1));
+	}
+
+	let res = eval(&args[0], env);
+	if let Err(e) = res {
+		Err(e)
+	} else if is_true(res.ok().unwrap()) {
+		Ok(DONE(LIST(NIL)))
+	} else {
+		progn(args.split_off(1), env)
 	}
 }
 
@@ -294,26 +612,26 @@ fn lambda(args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
 	} 
 
 	if let LIST(params) = args[0].clone() {
-		let mut arguments: Vec<String> = vec![];
-		let mut optn_args: Vec<(String, Datum)> = vec![];
-		let mut key_args: Vec<(String, Datum)> = vec![];
-		let mut rest_arg: Option<String> = None;
+		let mut arguments: Vec<Symbol> = vec![];
+		let mut optn_args: Vec<(Symbol, Datum)> = vec![];
+		let mut key_args: Vec<(Symbol, Datum)> = vec![];
+		let mut rest_arg: Option<Symbol> = None;
 		let params = params.get_items();
 		let mut mode: usize = 0; //(0 - normal, 1 - optional,
 								 // 2 - rest, 3 - key)
 
 		for param in params {
 			if let ATOM(SYMBOL(name)) = param {
-				if name == "&OPTIONAL".to_string() {
+				if name.name().to_uppercase() == "&OPTIONAL" {
 					mode = 1
-				} else if name == "&REST".to_string() {
+				} else if name.name().to_uppercase() == "&REST" {
 					mode = 2
-				} else if name == "&KEY".to_string() {
+				} else if name.name().to_uppercase() == "&KEY" {
 					mode = 3
 				} else if mode == 1 {
 					optn_args.push((name, LIST(NIL)))
 				} else if mode == 2 {
-					if rest_arg == None {
+					if rest_arg.is_none() {
 						rest_arg = Some(name);
 					} else {
 						return Err(MULTIPLE_REST_ARGS);
@@ -326,7 +644,7 @@ fn lambda(args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
 			} else if let LIST(lst) = param.clone() {
 				let items = lst.get_items();
 				if items.len() != 2 {
-					return Err(INVALID_ARGUMENT_TYPE(param, "list of length 2"));
+					return Err(INVALID_ARGUMENT_TYPE(Box::new(param), "list of length 2"));
 				} else if let ATOM(SYMBOL(name)) = items[0].clone() {
 					if mode == 1 {
 						optn_args.push((name, items[1].clone()))
@@ -336,47 +654,202 @@ fn lambda(args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
 						return Err(MISPLACED_DEFAULT_VALUE);
 					}
 				} else {
-					return Err(INVALID_ARGUMENT_TYPE(items[1].clone(), "symbol"));
+					return Err(INVALID_ARGUMENT_TYPE(Box::new(items[1].clone()), "symbol"));
 				}
 			} else {
-				return Err(INVALID_ARGUMENT_TYPE(param, "symbol"));
+				return Err(INVALID_ARGUMENT_TYPE(Box::new(param), "symbol"));
 			}
 		}
 
-		return Ok(FUNCTION(LAMBDA(
+		Ok(FUNCTION(LAMBDA(
 					Lambda{args: arguments,
 						   optn: optn_args,
 						   key:  key_args,
 						   rest: rest_arg,
 						   body: Box::new(args[1].clone()),
-						   env: env.top().into_iter()
-						   		   .filter(|&(ref key, _)| 
-						   		   		lambda_contains(key.clone(),
-						   		   						args[1].clone()))
-						   		   .collect()}
-					)));
+						   env: env.locals(),
+						   name: None,
+						   doc: None}
+					)))
 	} else {
-		return Err(INVALID_ARGUMENT_TYPE(args[0].clone(), "list"));
+		Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "list"))
+	}
+}
+
+//defun and defmacro take an optional docstring between the lambda list and
+//the body
+fn docstring(args: &[Datum]) -> Result<Option<String>, LispError> {
+	match args.len() {
+		3 => Ok(None),
+		4 => match args[2] {
+			ATOM(STRING(ref doc))	=> Ok(Some(doc.clone())),
+			ref e					=> Err(INVALID_ARGUMENT_TYPE(Box::new(e.clone()), "string"))
+		},
+		e => Err(INVALID_NUMBER_OF_ARGS(e, 3))
+	}
+}
+
+//Gives a lambda or macro the name it is defined under
+fn named(func: Datum, name: Symbol, doc: Option<String>) -> Datum {
+	match func {
+		FUNCTION(LAMBDA(mut l))	=> {
+			l.name = Some(name);
+			l.doc = doc;
+			FUNCTION(LAMBDA(l))
+		},
+		FUNCTION(MACRO(mut m))	=> {
+			m.name = Some(name);
+			m.doc = doc;
+			FUNCTION(MACRO(m))
+		},
+		e						=> e
 	}
 }
 
 fn defun(args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
-	if args.len() != 3 {
-		return Err(INVALID_NUMBER_OF_ARGS(args.len(), 3));
-	} 
+	let doc = docstring(&args)?;
+
+	let body = expand_in(args.last().unwrap(), &mut bound_names(&args[1]), env)?;
 
-	let lam = lambda(vec!(args[1].clone(), args[2].clone()), env);
+	let lam = lambda(vec!(args[1].clone(), body), env);
 	if lam.is_err() {
-		return lam;
+		lam
 	} else if let ATOM(SYMBOL(name)) = args[0].clone() {
 		match env.get(&name) {
-			Ok(FUNCTION(SPECIAL(_))) | Ok(FUNCTION(NATIVE(_))) 
-				=> return Err(OVERRIDE_RESERVED(name)),
-			_	=> return Ok(env.set(name, lam.ok().unwrap()))
+			Ok(FUNCTION(SPECIAL(_))) | Ok(FUNCTION(NATIVE(_))) | Ok(FUNCTION(FOREIGN(_)))
+				=> Err(OVERRIDE_RESERVED(name.to_string())),
+			_	=> Ok(env.set(name, named(lam.ok().unwrap(), name, doc)))
 		}
 	} else {
-		return Err(INVALID_ARGUMENT_TYPE(args[0].clone(), "symbol"))
+		Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "symbol"))
+	}
+}
+
+//(defstruct point x (y 0)) defines MAKE-POINT, which takes the fields as &key
+//arguments, POINT?, and POINT-X and SET-POINT-X! for each field
+fn defstruct(args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
+	let name = match args.first() {
+		Some(&ATOM(SYMBOL(name)))	=> name,
+		Some(e)						=> return Err(INVALID_ARGUMENT_TYPE(Box::new(e.clone()), "symbol")),
+		None						=> return Err(INVALID_NUMBER_OF_ARGS(0, 1))
+	};
+	let mut fields = vec![];
+	for spec in &args[1..] {
+		match *spec {
+			ATOM(SYMBOL(field))	=> fields.push(field),
+			LIST(ref l) if l.get_items().len() == 2 => match l.car() {
+				ATOM(SYMBOL(field))	=> fields.push(field),
+				e					=> return Err(INVALID_ARGUMENT_TYPE(Box::new(e), "symbol"))
+			},
+			_					=> return Err(INVALID_ARGUMENT_TYPE(Box::new(spec.clone()), "symbol or (symbol default)"))
+		}
+	}
+
+	//generated names follow the case the struct name was read in
+	let preserve = env.preserve_case();
+	let fn_name = |s: String| ATOM(SYMBOL(Symbol::intern(&if preserve {s.to_lowercase()} else {s})));
+	let sym = |s: &str| ATOM(SYMBOL(Symbol::intern(s)));
+	let quoted = |d: Datum| LIST(List::from_vec(vec![sym("QUOTE"), d]));
+	let form = |items: Vec<Datum>| LIST(List::from_vec(items));
+	let type_name = quoted(ATOM(SYMBOL(name)));
+
+	let mut params = vec![sym("&KEY")];
+	params.extend(args[1..].iter().cloned());
+	let mut make = vec![sym("MAKE-STRUCT"), type_name.clone()];
+	for field in &fields {
+		make.push(quoted(ATOM(SYMBOL(*field))));
+		make.push(ATOM(SYMBOL(*field)));
+	}
+	let mut defs = vec![
+		(fn_name(format!("MAKE-{}", name)), form(params), form(make)),
+		(fn_name(format!("{}?", name)), form(vec![sym("OBJ")]),
+		 form(vec![sym("STRUCT?"), sym("OBJ"), type_name.clone()]))
+	];
+	for field in &fields {
+		let field_name = quoted(ATOM(SYMBOL(*field)));
+		defs.push((fn_name(format!("{}-{}", name, field)), form(vec![sym("OBJ")]),
+				   form(vec![sym("STRUCT-REF"), sym("OBJ"), field_name.clone(), type_name.clone()])));
+		defs.push((fn_name(format!("SET-{}-{}!", name, field)), form(vec![sym("OBJ"), sym("VALUE")]),
+				   form(vec![sym("STRUCT-SET!"), sym("OBJ"), field_name, sym("VALUE"), type_name.clone()])));
+	}
+	for (fn_name, params, body) in defs {
+		defun(vec![fn_name, params, body], env)?;
+	}
+	Ok(ATOM(SYMBOL(name)))
+}
+
+//(handler-case form (type (var) body...) ...) evaluates form, and if it raises
+//a condition the first clause whose type matches runs with var bound to the
+//condition object. ERROR catches every error and CONDITION anything at all.
+fn handler_case(args: Vec<Datum>, env: &mut Env) -> Result<Step, LispError> {
+	if args.is_empty() {
+		return Err(INVALID_NUMBER_OF_ARGS(0, 1));
+	}
+	let mut clauses = vec![];
+	for clause in &args[1..] {
+		let items = match *clause {
+			LIST(ref lst) if lst.get_items().len() >= 2 => lst.get_items(),
+			_ => return Err(INVALID_ARGUMENT_TYPE(Box::new(clause.clone()), "(type (var) body...)"))
+		};
+		let var = match items[1] {
+			LIST(NIL)	=> None,
+			LIST(ref lst) if lst.get_items().len() == 1 => match lst.car() {
+				ATOM(SYMBOL(var))	=> Some(var),
+				e					=> return Err(INVALID_ARGUMENT_TYPE(Box::new(e), "symbol"))
+			},
+			ref e		=> return Err(INVALID_ARGUMENT_TYPE(Box::new(e.clone()), "list of length 1"))
+		};
+		match items[0] {
+			ATOM(SYMBOL(ty))	=> clauses.push((ty, var, items[2..].to_vec())),
+			ATOM(T)				=> clauses.push((Symbol::intern("T"), var, items[2..].to_vec())),
+			ref e				=> return Err(INVALID_ARGUMENT_TYPE(Box::new(e.clone()), "symbol"))
+		}
+	}
+
+	env.push_handlers(clauses.iter().map(|&(ty, _, _)| ty).collect());
+	let res = eval(&args[0], env);
+	env.pop_handlers();
+	let err = match res {
+		Ok(val)	=> return Ok(DONE(val)),
+		Err(e)	=> e
+	};
+	for (ty, var, body) in clauses {
+		if err.handled_by(ty) {
+			let mut map = HashMap::new();
+			if let Some(var) = var {
+				map.insert(var, err.condition());
+			}
+			env.push_map(&map);
+			let mut form = vec![ATOM(SYMBOL(Symbol::intern("PROGN")))];
+			form.extend(body);
+			return Ok(PUSHED(LIST(List::from_vec(form))));
+		}
 	}
+	Err(err)
+}
+
+//Evaluates the forms like progn, returning NIL instead if an error is raised
+fn ignore_errors(args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
+	env.push_handlers(vec![Symbol::intern("ERROR")]);
+	let res = run(progn(args, env), env);
+	env.pop_handlers();
+	match res {
+		Err(ref e) if e.is_error()	=> Ok(LIST(NIL)),
+		res							=> res
+	}
+}
+
+//(unwind-protect form cleanup...) runs the cleanup forms however form exits,
+//then returns its value or raises its error again
+fn unwind_protect(args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
+	if args.is_empty() {
+		return Err(INVALID_NUMBER_OF_ARGS(0, 1));
+	}
+	let res = eval(&args[0], env);
+	for cleanup in &args[1..] {
+		eval(cleanup, env)?;
+	}
+	res
 }
 
 fn quote(args: Vec<Datum>) -> Result<Datum, LispError> {
@@ -397,19 +870,30 @@ fn backquote(args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
 
 fn backquote_helper(arg: &Datum, env: &mut Env) -> Result<Datum, LispError> {
 	match *arg {
-		ref e @ ATOM(_) | ref e @ FUNCTION(_) 	=> Ok(e.clone()),
+		ref e @ ATOM(_) | ref e @ FUNCTION(_) |
+		ref e @ VECTOR(_) | ref e @ HASH_TABLE(_) |
+		ref e @ STRUCT(_)						=> Ok(e.clone()),
 		LIST(NIL)								=> Ok(LIST(NIL)),
 		LIST(ref lst)							=> {
-			if lst.car() == ATOM(SYMBOL("COMMA".to_string())) {
+			if lst.car() == ATOM(SYMBOL(Symbol::intern("COMMA"))) {
 				eval(&lst.get_items()[1], env) //assumes list is of form (COMMA item)
 			} else {
-				let mut items = lst.get_items();
-				for i in 0..items.len() {
-					let res = backquote_helper(&items[i], env);
+				let mut items = Vec::new();
+				for item in lst.get_items() {
+					if let LIST(ref inner@CONS(..)) = item {
+						if inner.car() == ATOM(SYMBOL(Symbol::intern("COMMA-AT"))) {
+							match eval(&inner.get_items()[1], env) {
+								Ok(LIST(spliced))	=> {items.extend(spliced.get_items()); continue},
+								Ok(e)				=> return Err(INVALID_ARGUMENT_TYPE(Box::new(e), "list")),
+								Err(e)				=> return Err(e)
+							}
+						}
+					}
+					let res = backquote_helper(&item, env);
 					if res.is_err() {
 						return res;
 					} else {
-						items[i] = res.ok().unwrap();
+						items.push(res.ok().unwrap());
 					}
 				}
 
@@ -419,45 +903,43 @@ fn backquote_helper(arg: &Datum, env: &mut Env) -> Result<Datum, LispError> {
 	}
 }
 
-fn let_lisp(args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
+fn let_lisp(args: Vec<Datum>, env: &mut Env) -> Result<Step, LispError> {
 	if args.len() != 2 {
 		return Err(INVALID_NUMBER_OF_ARGS(args.len(), 2));
 	}
 
 	if let LIST(ref lst) = args[0] {
-		let mut map: HashMap<String, Datum> = HashMap::new();
+		let mut map: HashMap<Symbol, Datum> = HashMap::new();
 		for item in lst.get_items() {
 			if let LIST(lst) = item {
 				let binding = lst.get_items();
 				if binding.len() != 2 {
-					return Err(INVALID_ARGUMENT_TYPE(LIST(lst), "list of length 2"))
+					return Err(INVALID_ARGUMENT_TYPE(Box::new(LIST(lst)), "list of length 2"))
 				}
 
 				if let ATOM(SYMBOL(ref name)) = binding[0] {
 					let res = eval(&binding[1], env);
-					if res.is_err() {
-						return res;
+					if let Err(e) = res {
+						return Err(e);
 					} else {
-						map.insert(name.clone(), res.ok().unwrap());
+						map.insert(*name, res.ok().unwrap());
 					}
 				} else {
-					return Err(INVALID_ARGUMENT_TYPE(binding[0].clone(), "symbol"))
+					return Err(INVALID_ARGUMENT_TYPE(Box::new(binding[0].clone()), "symbol"))
 				}
 			} else {
-				return Err(INVALID_ARGUMENT_TYPE(item, "list"))
+				return Err(INVALID_ARGUMENT_TYPE(Box::new(item), "list"))
 			}
 		}
 
 		env.push_map(&map);
-		let res = eval(&args[1], env);
-		env.pop();
-		res
+		Ok(PUSHED(args[1].clone()))
 	} else {
-		Err(INVALID_ARGUMENT_TYPE(args[0].clone(), "list"))
+		Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "list"))
 	}
 }
 
-fn let_star(args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
+fn let_star(args: Vec<Datum>, env: &mut Env) -> Result<Step, LispError> {
 	if args.len() != 2 {
 		return Err(INVALID_NUMBER_OF_ARGS(args.len(), 2));
 	}
@@ -465,63 +947,92 @@ fn let_star(args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
 	if let LIST(ref lst) = args[0] {
 		env.push();
 		for item in lst.get_items() {
-			if let LIST(lst) = item {
+			let err = if let LIST(lst) = item {
 				let binding = lst.get_items();
 				if binding.len() != 2 {
-					return Err(INVALID_ARGUMENT_TYPE(LIST(lst), "list of length 2"))
-				}
-
-				if let ATOM(SYMBOL(ref name)) = binding[0] {
-					let res = eval(&binding[1], env);
-					if res.is_err() {
-						return res;
-					} else {
-						env.set(name.clone(), res.ok().unwrap());
+					INVALID_ARGUMENT_TYPE(Box::new(LIST(lst)), "list of length 2")
+				} else if let ATOM(SYMBOL(ref name)) = binding[0] {
+					match eval(&binding[1], env) {
+						Ok(val)	=> {
+							env.set(*name, val);
+							continue;
+						},
+						Err(e)	=> e
 					}
 				} else {
-					return Err(INVALID_ARGUMENT_TYPE(binding[0].clone(), "symbol"))
+					INVALID_ARGUMENT_TYPE(Box::new(binding[0].clone()), "symbol")
 				}
 			} else {
-				return Err(INVALID_ARGUMENT_TYPE(item, "list"))
-			}
+				INVALID_ARGUMENT_TYPE(Box::new(item), "list")
+			};
+			env.pop();
+			return Err(err);
 		}
 
-		let res = eval(&args[1], env);
-		env.pop();
-		res
+		Ok(PUSHED(args[1].clone()))
 	} else {
-		Err(INVALID_ARGUMENT_TYPE(args[0].clone(), "list"))
+		Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "list"))
 	}
 }
 
-fn progn(args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
-	let mut res = Ok(LIST(NIL));
+fn progn(mut args: Vec<Datum>, env: &mut Env) -> Result<Step, LispError> {
+	let last = match args.pop() {
+		Some(form)	=> form,
+		None		=> return Ok(DONE(LIST(NIL)))
+	};
 	for item in args {
-		res = eval(&item, env);
-		if res.is_err() {
-			return res;
-		}
+		eval(&item, env)?;
 	}
-	res
+	Ok(TAIL(last))
 }
 
+//An optional second argument of UPCASE or PRESERVE overrides the reader's case
+//mode for this file
 pub fn load(args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
-	if args.len() != 1 {
-		Err(INVALID_NUMBER_OF_ARGS(args.len(), 1))
-	} else if let ATOM(STRING(ref file_path)) = args[0] {
+	let preserve_case = match args.get(1) {
+		None		=> env.preserve_case(),
+		Some(mode)	=> read_case_arg(mode)?
+	};
+
+	if let ATOM(STRING(ref file_path)) = args[0] {
 		let mut file = match File::open(file_path.clone()) {
 			Ok(f) 		=> f,
 			Err(why) 	=> return Err(CANNOT_OPEN_FILE(format!("{}", why)))
 		};
 		let mut contents = String::new();
 
-		file.read_to_string(&mut contents).ok().expect("Failed read file to string");
-		eval(&parse(&mut tokenize(&format!("(progn {})", contents))), env)
+		file.read_to_string(&mut contents).expect("Failed read file to string");
+		eval_source(&contents, file_path, preserve_case, env)
 	} else {
-		Err(INVALID_ARGUMENT_TYPE(args[0].clone(), "string"))
+		Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "string"))
 	}
 }
 
+//Evaluates every form in the source, returning the value of the last one. The
+//name is used in the spans of errors.
+pub fn eval_source(source: &str, name: &str, preserve_case: bool, env: &mut Env) -> Result<Datum, LispError> {
+	//each form is expanded right before it runs so that it can use macros
+	//defined earlier in the source
+	let mut tkns = tokenize(source, name, preserve_case)?;
+	let mut res = Ok(LIST(NIL));
+	loop {
+		if let Err(e) = skip_datum_comments(&mut tkns) {
+			return Err(e);
+		} else if tkns.is_empty() {
+			break;
+		}
+		let span = tkns[0].span.clone();
+		res = macroexpand_all(&parse(&mut tkns)?, env);
+		if res.is_ok() {
+			res = eval(&res.ok().unwrap(), env);
+		}
+		if let Err(e) = res {
+			return Err(LOCATED(span, Box::new(e)));
+		}
+	}
+	res
+}
+
 pub fn time(args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
 	if args.len() != 1 {
 		Err(INVALID_NUMBER_OF_ARGS(args.len(), 1))
@@ -533,55 +1044,222 @@ pub fn time(args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
 	}
 }
 
+//With no arguments returns the reader's current case mode, otherwise sets it
+//to either UPCASE or PRESERVE
+pub fn reader_case(args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
+	if let Some(mode) = args.first() {
+		match read_case_arg(mode) {
+			Ok(preserve)	=> env.set_preserve_case(preserve),
+			Err(e)			=> return Err(e)
+		}
+	}
+	let mode = if env.preserve_case() {"PRESERVE"} else {"UPCASE"};
+	Ok(ATOM(SYMBOL(Symbol::intern(mode))))
+}
+
+//Reads the symbol UPCASE or PRESERVE, in any case, as whether to preserve case
+fn read_case_arg(arg: &Datum) -> Result<bool, LispError> {
+	match *arg {
+		ATOM(SYMBOL(ref s)) if s.name().to_uppercase() == "UPCASE"		=> Ok(false),
+		ATOM(SYMBOL(ref s)) if s.name().to_uppercase() == "PRESERVE"	=> Ok(true),
+		_ => Err(INVALID_ARGUMENT_TYPE(Box::new(arg.clone()), "reader case (UPCASE or PRESERVE)"))
+	}
+}
+
 pub fn set(args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
-	if args.len() != 2 {
-		Err(INVALID_NUMBER_OF_ARGS(args.len(), 2))
-	} else if let ATOM(SYMBOL(name)) = args[0].clone() {
+	if let ATOM(SYMBOL(name)) = args[0].clone() {
 		match env.get(&name) {
-			Ok(FUNCTION(SPECIAL(_))) | Ok(FUNCTION(NATIVE(_))) 
-				=> return Err(OVERRIDE_RESERVED(name)),
-			_ 	=> return Ok(env.set_bot(name, args[1].clone()))
+			Ok(FUNCTION(SPECIAL(_))) | Ok(FUNCTION(NATIVE(_))) | Ok(FUNCTION(FOREIGN(_)))
+				=> Err(OVERRIDE_RESERVED(name.to_string())),
+			_	=> Ok(env.assign(name, args[1].clone()))
 		}
 	} else {
-		Err(INVALID_ARGUMENT_TYPE(args[0].clone(), "symbol"))
+		Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "symbol"))
 	}
 }
 
-pub fn gensym(args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
-	if args.len() != 0 {
-		return Err(INVALID_NUMBER_OF_ARGS(args.len(), 0));
-	}
-	for num in 0.. {
-		let sym = format!(":G{}", num);
-		if let Err(..) = env.get(&sym) {
+pub fn gensym(env: &mut Env) -> Result<Datum, LispError> {
+	loop {
+		let sym = Symbol::intern(&format!("#:G{}", env.next_gensym()));
+		if env.get(&sym).is_err() {
 			return Ok(ATOM(SYMBOL(sym)));
 		}
 	}
-	Err(NO_INPUT) //never reached
 }
 
 pub fn apply_lisp(args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
-	if args.len() != 2 {
-		Err(INVALID_NUMBER_OF_ARGS(args.len(), 2))
-	} else if let FUNCTION(func) = args[0].clone() {
+	if let FUNCTION(func) = args[0].clone() {
 		if let LIST(lst) = args[1].clone() {
 			apply(func, lst.get_items(), env)
 		} else {
-			Err(INVALID_ARGUMENT_TYPE(args[1].clone(), "list"))
+			Err(INVALID_ARGUMENT_TYPE(Box::new(args[1].clone()), "list"))
 		}
 	} else {
-		Err(INVALID_ARGUMENT_TYPE(args[0].clone(), "function"))
+		Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "function"))
 	}
 }
 
-pub fn eval_lisp(args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
-	if args.len() != 1 {
-		Err(INVALID_NUMBER_OF_ARGS(args.len(), 1))
+//Each element is quoted so that apply doesn't evaluate it a second time
+pub fn vector_map(args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
+	let items = match vector_arg(&args[1]) {
+		Ok(v)	=> v.borrow().clone(),
+		Err(e)	=> return Err(e)
+	};
+	if let FUNCTION(ref func) = args[0] {
+		let mut ret = Vec::with_capacity(items.len());
+		for item in items {
+			let quoted = LIST(List::from_vec(vec![ATOM(SYMBOL(Symbol::intern("QUOTE"))), item]));
+			ret.push(apply(func.clone(), vec![quoted], env)?);
+		}
+		Ok(Datum::vector(ret))
+	} else {
+		Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "function"))
+	}
+}
+
+//Unlike error, a signal nothing is waiting for just returns NIL
+fn signal(args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
+	let condition = match make_condition(args, "SIMPLE-CONDITION") {
+		Ok(c)	=> SIGNAL(Box::new(c)),
+		Err(e)	=> return Err(e)
+	};
+	if env.is_handled(&condition) {
+		Err(condition)
+	} else {
+		Ok(LIST(NIL))
+	}
+}
+
+//Calls the function with each key and value, in insertion order
+pub fn hash_for_each(args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
+	let entries = match hash_table_arg(&args[0]) {
+		Ok(h)	=> h.borrow().entries().cloned().collect::<Vec<_>>(),
+		Err(e)	=> return Err(e)
+	};
+	if let FUNCTION(ref func) = args[1] {
+		let quote = ATOM(SYMBOL(Symbol::intern("QUOTE")));
+		for (key, value) in entries {
+			apply(func.clone(), vec![LIST(List::from_vec(vec![quote.clone(), key])),
+									 LIST(List::from_vec(vec![quote.clone(), value]))], env)?;
+		}
+		Ok(LIST(NIL))
 	} else {
-		eval(&args[0], env)
+		Err(INVALID_ARGUMENT_TYPE(Box::new(args[1].clone()), "function"))
+	}
+}
+
+//Symbols are looked up, so both (describe 'fib) and (describe fib) work
+fn described(args: &[Datum], env: &Env) -> Result<(Option<Symbol>, Datum), LispError> {
+	match args[0] {
+		ATOM(SYMBOL(sym))	=> env.get(&sym).map(|val| (Some(sym), val)),
+		ref e				=> Ok((None, e.clone()))
 	}
 }
 
+//Prints what kind of object the argument is, and for functions their
+//lambda list or signature and their documentation
+pub fn describe(args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
+	description(&args, env).map(|text| {
+		println!("{}", text);
+		LIST(NIL)
+	})
+}
+
+fn description(args: &[Datum], env: &Env) -> Result<String, LispError> {
+	let (sym, val) = described(args, env)?;
+	let lambda_name = |l: &Lambda| match l.name.or(sym) {
+		Some(name)	=> format!("{} is a", name),
+		None		=> "An anonymous".to_string()
+	};
+	let builtin = |kind: &str, doc: Doc| format!("{} is a {}\nSignature: {}\nTakes {}",
+		sym.map_or(doc.name().to_uppercase(), |s| s.to_string()), kind, doc.signature, Arity(doc.min_args, doc.max_args));
+	let mut text = match val {
+		FUNCTION(LAMBDA(ref l))	=> format!("{} function\nLambda list: {}", lambda_name(l), l.lambda_list()),
+		FUNCTION(MACRO(ref m))	=> format!("{} macro\nLambda list: {}", lambda_name(m), m.lambda_list()),
+		FUNCTION(NATIVE(n))		=> builtin("native function", n.doc()),
+		FUNCTION(SPECIAL(s))	=> builtin("special form", s.doc()),
+		FUNCTION(FOREIGN(ref r))	=> format!("{} is a foreign function", sym.unwrap_or(r.name)),
+		ref e					=> {
+			let ty = type_lisp(vec![e.clone()]).unwrap_or(LIST(NIL));
+			match sym {
+				Some(sym)	=> format!("{} is a variable of type {} with value {}", sym, ty, e),
+				None		=> format!("{} is of type {}", e, ty)
+			}
+		}
+	};
+	if let ATOM(STRING(doc)) = documentation_of(&val) {
+		text = format!("{}\n\n{}", text, doc);
+	}
+	Ok(text)
+}
+
+fn documentation_of(val: &Datum) -> Datum {
+	match *val {
+		FUNCTION(LAMBDA(Lambda{doc: Some(ref doc), ..})) |
+		FUNCTION(MACRO(Lambda{doc: Some(ref doc), ..}))	=> ATOM(STRING(doc.clone())),
+		FUNCTION(NATIVE(n))								=> ATOM(STRING(n.doc().description.to_string())),
+		FUNCTION(SPECIAL(s))							=> ATOM(STRING(s.doc().description.to_string())),
+		_												=> LIST(NIL)
+	}
+}
+
+//The docstring given to defun or defmacro, the description of a builtin, or NIL
+pub fn documentation(args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
+	described(&args, env).map(|(_, val)| documentation_of(&val))
+}
+
+//Describes its argument, or explains how to look things up when called without one
+pub fn help(args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
+	if !args.is_empty() {
+		return describe(args, env);
+	}
+	println!("(help 'name) shows the signature and documentation of a function or special form.");
+	println!("(apropos \"text\") lists every bound symbol whose name contains the text.");
+	Ok(LIST(NIL))
+}
+
+//Prints each bound symbol whose name contains the string, ignoring case,
+//with its signature or lambda list
+pub fn apropos(args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
+	matching_names(&args, env).map(|lines| {
+		for line in lines {
+			println!("{}", line);
+		}
+		LIST(NIL)
+	})
+}
+
+fn matching_names(args: &[Datum], env: &Env) -> Result<Vec<String>, LispError> {
+	let pattern = match args[0] {
+		ATOM(STRING(ref s))	=> s.to_uppercase(),
+		ATOM(SYMBOL(s))		=> s.name().to_uppercase(),
+		ref e				=> return Err(INVALID_ARGUMENT_TYPE(Box::new(e.clone()), "string"))
+	};
+	let mut found: Vec<(String, Datum)> = env.join().into_iter()
+		.map(|(sym, val)| (sym.to_string(), val))
+		.filter(|(name, _)| name.to_uppercase().contains(&pattern[..]))
+		.collect();
+	//inner frames come last in join, so keep the innermost binding of each name
+	found.reverse();
+	found.sort_by(|a, b| a.0.cmp(&b.0));
+	found.dedup_by(|a, b| a.0 == b.0);
+	Ok(found.into_iter().map(|(name, val)| {
+		let summary = match val {
+			FUNCTION(LAMBDA(ref l))	=> format!("function {}", l.lambda_list()),
+			FUNCTION(MACRO(ref m))	=> format!("macro {}", m.lambda_list()),
+			FUNCTION(NATIVE(n))		=> n.doc().signature.to_string(),
+			FUNCTION(SPECIAL(s))	=> format!("{} [special form]", s.doc().signature),
+			FUNCTION(FOREIGN(_))	=> "foreign function".to_string(),
+			_						=> "variable".to_string()
+		};
+		format!("{:<24} {}", name, summary)
+	}).collect())
+}
+
+pub fn eval_lisp(args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
+	eval(&args[0], env)
+}
+
 fn macro_lisp(args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
 	let lam = lambda(args, env);
 	if let Ok(FUNCTION(LAMBDA(mac))) = lam {
@@ -592,21 +1270,151 @@ fn macro_lisp(args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
 }
 
 fn defmacro(args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
-	if args.len() != 3 {
-		return Err(INVALID_NUMBER_OF_ARGS(args.len(), 3));
-	} 
+	let doc = docstring(&args)?;
 
-	let mac = macro_lisp(vec!(args[1].clone(), args[2].clone()), env);
+	let mac = macro_lisp(vec!(args[1].clone(), args.last().unwrap().clone()), env);
 	if mac.is_err() {
-		return mac;
+		mac
 	} else if let ATOM(SYMBOL(name)) = args[0].clone() {
 		match env.get(&name) {
-			Ok(FUNCTION(SPECIAL(_))) | Ok(FUNCTION(NATIVE(_))) 
-				=> return Err(OVERRIDE_RESERVED(name)),
-			_	=> return Ok(env.set(name, mac.ok().unwrap()))
+			Ok(FUNCTION(SPECIAL(_))) | Ok(FUNCTION(NATIVE(_))) | Ok(FUNCTION(FOREIGN(_)))
+				=> Err(OVERRIDE_RESERVED(name.to_string())),
+			_	=> Ok(env.set(name, named(mac.ok().unwrap(), name, doc)))
 		}
 	} else {
-		return Err(INVALID_ARGUMENT_TYPE(args[0].clone(), "symbol"))
+		Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "symbol"))
+	}
+}
+
+//Same as defmacro, except that variables bound by let, let* or lambda forms
+//inside the macro's backquoted templates are renamed to fresh gensyms every
+//time the macro is expanded, so they can never capture the caller's symbols
+fn defmacro_hygienic(mut args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
+	docstring(&args)?;
+	let body = hygienic(args.pop().unwrap(), env);
+	args.push(body);
+	defmacro(args, env)
+}
+
+fn is_symbol_named(form: &Datum, name: &str) -> bool {
+	match *form {
+		ATOM(SYMBOL(ref s))	=> s.name().to_uppercase() == name,
+		_					=> false
+	}
+}
+
+fn is_unquote(form: &Datum) -> bool {
+	match *form {
+		LIST(ref lst@CONS(..))	=> is_symbol_named(&lst.car(), "COMMA") ||
+								   is_symbol_named(&lst.car(), "COMMA-AT"),
+		_						=> false
+	}
+}
+
+//Quoted forms, and templates nested inside this one, are data the template
+//hands back untouched rather than code that binds anything
+fn is_quoted(form: &Datum) -> bool {
+	match *form {
+		LIST(ref lst@CONS(..))	=> is_symbol_named(&lst.car(), "QUOTE") ||
+								   is_symbol_named(&lst.car(), "BACKQUOTE"),
+		_						=> false
+	}
+}
+
+//Wraps every backquoted template in the macro body in a let that binds a
+//gensym for each variable the template introduces, and splices those gensyms
+//into the template in place of the original names
+fn hygienic(form: Datum, env: &mut Env) -> Datum {
+	match form {
+		LIST(lst@CONS(..)) => {
+			let items = lst.get_items();
+			if is_symbol_named(&items[0], "BACKQUOTE") && items.len() == 2 {
+				let mut binders = vec![];
+				template_binders(&items[1], &mut binders);
+				if binders.is_empty() {
+					return LIST(lst);
+				}
+
+				let mut renames = vec![];
+				let mut bindings = vec![];
+				for name in binders {
+					let holder = Symbol::intern(&format!("#:G{}", env.next_gensym()));
+					bindings.push(LIST(List::from_vec(vec![
+						ATOM(SYMBOL(holder)),
+						LIST(List::from_vec(vec![ATOM(SYMBOL(Symbol::intern("GENSYM")))]))])));
+					renames.push((name, holder));
+				}
+				let template = rename_template(items[1].clone(), &renames);
+				LIST(List::from_vec(vec![
+					ATOM(SYMBOL(Symbol::intern("LET"))),
+					LIST(List::from_vec(bindings)),
+					LIST(List::from_vec(vec![items[0].clone(), template]))]))
+			} else {
+				LIST(List::from_vec(items.into_iter().map(|item| hygienic(item, env)).collect()))
+			}
+		},
+		e => e
+	}
+}
+
+fn template_binders(template: &Datum, binders: &mut Vec<Symbol>) {
+	if let LIST(ref lst@CONS(..)) = *template {
+		if is_unquote(template) || is_quoted(template) {
+			return;
+		}
+		let items = lst.get_items();
+		let mut found = vec![];
+		//names spliced in with , or ,@ come from the macro's caller and are left alone
+		let written = match items.get(1) {
+			Some(LIST(lst)) if !is_unquote(&items[1])		=> lst.get_items().into_iter()
+																   .filter(|item| !is_unquote(item)).collect(),
+			_												=> vec![]
+		};
+		if is_symbol_named(&items[0], "LET") || is_symbol_named(&items[0], "LET*") {
+			for binding in written {
+				if let LIST(ref pair@CONS(..)) = binding {
+					found.push(pair.car());
+				}
+			}
+		} else if is_symbol_named(&items[0], "LAMBDA") || is_symbol_named(&items[0], "MACRO") {
+			for param in written {
+				match param {
+					LIST(ref pair@CONS(..))	=> found.push(pair.car()),
+					e						=> found.push(e)
+				}
+			}
+		}
+		for binder in found {
+			if let ATOM(SYMBOL(name)) = binder {
+				if !name.name().starts_with('&') && !binders.contains(&name) {
+					binders.push(name);
+				}
+			}
+		}
+		for item in items {
+			template_binders(&item, binders);
+		}
+	}
+}
+
+fn rename_template(template: Datum, renames: &Vec<(Symbol, Symbol)>) -> Datum {
+	if is_unquote(&template) || is_quoted(&template) {
+		return template;
+	}
+	match template {
+		ATOM(SYMBOL(name)) => {
+			match renames.iter().find(|&(old, _)| *old == name) {
+				Some((_, holder))		=> LIST(List::from_vec(vec![
+											ATOM(SYMBOL(Symbol::intern("COMMA"))),
+											ATOM(SYMBOL(*holder))])),
+				None					=> ATOM(SYMBOL(name))
+			}
+		},
+		LIST(lst@CONS(..)) => {
+			LIST(List::from_vec(lst.get_items().into_iter()
+				.map(|item| rename_template(item, renames)).collect()))
+		},
+		e => e
 	}
 }
 
@@ -619,90 +1427,353 @@ fn macroexpand(args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
 		if func.is_err() {
 			func
 		} else if let Ok(FUNCTION(MACRO(mac))) = func {
-			macroexpand_helper(&mac, items[1..].to_vec())
+			macroexpand_helper(&mac, items[1..].to_vec(), env)
 		} else {
-			Err(INVALID_ARGUMENT_TYPE(func.ok().unwrap(), "macro"))
+			Err(INVALID_ARGUMENT_TYPE(Box::new(func.ok().unwrap()), "macro"))
 		}
 	} else {
-		Err(INVALID_ARGUMENT_TYPE(args[0].clone(), "nonempty list"))
+		Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "nonempty list"))
 	}
 }
 
-fn sublis(old: &Datum, new: &Datum, tree: Datum) -> Datum {
-	if tree == *old {
-		new.clone()
-	} else if let LIST(CONS(ref car, ref cdr)) = tree {
-		LIST(CONS(
-			Box::new(sublis(old, new, *car.clone())),
-			Box::new(sublis(old, new, *cdr.clone()))))
+fn macroexpand_all_lisp(args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
+	if args.len() != 1 {
+		Err(INVALID_NUMBER_OF_ARGS(args.len(), 1))
 	} else {
-		tree
+		macroexpand_all(&args[0], env)
 	}
 }
 
-fn macroexpand_helper(func: &Lambda, args: Vec<Datum>) -> Result<Datum, LispError> {
-	if args.len() < func.args.len() {
-		return Err(INVALID_NUMBER_OF_ARGS(args.len(), func.args.len()));
+//Walks a form and replaces every macro call in it with its expansion, so the
+//expansion happens once instead of every time the form is evaluated. Quoted
+//data, parameter lists and macro definitions are left untouched.
+//Expanded lists keep the location of the form they came from, so calls inside
+//macros and function bodies still show where they were written
+pub fn macroexpand_all(form: &Datum, env: &mut Env) -> Result<Datum, LispError> {
+	expand_in(form, &mut vec![], env)
+}
+
+//Expands a form in which the given names are lexically bound. A call whose head
+//is one of them refers to the local variable, not to a macro or special form.
+fn expand_in(form: &Datum, bound: &mut Vec<Symbol>, env: &mut Env) -> Result<Datum, LispError> {
+	let res = expand_form(form, bound, env);
+	if let (LIST(old), &Ok(LIST(ref new))) = (form, &res) {
+		if let (Some(span), None) = (old.span(), new.span()) {
+			new.set_span(span);
+		}
 	}
+	res
+}
 
-	let mut params: Vec<Datum> = Vec::with_capacity(func.args.len());
-	let mut optional_params: Vec<Datum> = Vec::with_capacity(func.optn.len());
-	let mut key_params: Vec<(String, Datum)> = Vec::with_capacity(func.key.len());
-	let mut rest_params: Vec<Datum> = Vec::new();
+//Names bound by a lambda list or by the bindings of a let, skipping &optional
+//and the other lambda list keywords
+fn bound_names(bindings: &Datum) -> Vec<Symbol> {
+	match *bindings {
+		LIST(ref lst)	=> lst.get_items().iter().filter_map(binding_name).collect(),
+		_				=> vec![]
+	}
+}
 
-	for i in 0..func.args.len() {
-		params.push(args[i].clone())
+//The name in a binding written as name or (name value)
+fn binding_name(binding: &Datum) -> Option<Symbol> {
+	match *binding {
+		ATOM(SYMBOL(name)) if !name.name().starts_with('&')	=> Some(name),
+		LIST(ref lst@CONS(..))								=> match lst.car() {
+			ATOM(SYMBOL(name))	=> Some(name),
+			_					=> None
+		},
+		_													=> None
 	}
+}
 
-	let mut is_key = false;
-	let mut key_name = "";
-	for i in func.args.len()..args.len() {
-		if let ATOM(SYMBOL(ref name)) = args[i] {
-			if name.starts_with(':') && i != args.len()-1 {
-				let name = &name[1..];
-				if func.contains_key(name.to_string()) && !is_key {
-					if !key_params.clone().into_iter().any(|(key, _): (String,_)| key==":".to_string()+name) {
-						key_name = &name;
-						is_key = true;
-						continue;
+//Expands the items of a form from the given index with more names bound
+fn expand_binding(items: Vec<Datum>, start: usize, names: Vec<Symbol>, bound: &mut Vec<Symbol>,
+				  env: &mut Env) -> Result<Datum, LispError> {
+	let depth = bound.len();
+	bound.extend(names);
+	let res = expand_from(items, start, bound, env);
+	bound.truncate(depth);
+	res
+}
+
+fn expand_form(form: &Datum, bound: &mut Vec<Symbol>, env: &mut Env) -> Result<Datum, LispError> {
+	let lst = match *form {
+		LIST(ref lst@CONS(..))	=> lst,
+		_						=> return Ok(form.clone())
+	};
+	let items = lst.get_items();
+	let head = match items[0] {
+		ATOM(SYMBOL(ref name)) if !bound.contains(name)	=> env.get(name).ok(),
+		_												=> None
+	};
+
+	match head {
+		Some(FUNCTION(MACRO(ref mac))) => {
+			let res = macroexpand_helper(mac, items[1..].to_vec(), env);
+			if res.is_err() {
+				res
+			} else {
+				expand_in(&res.ok().unwrap(), bound, env)
+			}
+		},
+		Some(FUNCTION(SPECIAL(QUOTE))) | Some(FUNCTION(SPECIAL(MACROEXPAND))) |
+		Some(FUNCTION(SPECIAL(MACROEXPAND_ALL))) | Some(FUNCTION(SPECIAL(DEFMACRO))) |
+		Some(FUNCTION(SPECIAL(DEFMACRO_HYGIENIC))) | Some(FUNCTION(SPECIAL(MACRO_FUNC))) |
+		Some(FUNCTION(SPECIAL(DEFSTRUCT)))
+			=> Ok(form.clone()),
+		Some(FUNCTION(SPECIAL(BACKQUOTE))) if items.len() == 2 => {
+			let res = expand_unquoted(&items[1], bound, env);
+			if res.is_err() {
+				res
+			} else {
+				Ok(LIST(List::from_vec(vec![items[0].clone(), res.ok().unwrap()])))
+			}
+		},
+		Some(FUNCTION(SPECIAL(CASE))) if items.len() > 1 => {
+			let mut items = items;
+			for (i, item) in items.iter_mut().enumerate().skip(1) {
+				*item = match item.clone() {
+					LIST(ref clause@CONS(..)) if i > 1	=> expand_from(clause.get_items(), 1, bound, env)?,
+					e if i == 1							=> expand_in(&e, bound, env)?,
+					e									=> e
+				};
+			}
+			Ok(LIST(List::from_vec(items)))
+		},
+		Some(FUNCTION(SPECIAL(HANDLER_CASE))) if items.len() > 1 => {
+			let mut items = items;
+			for (i, item) in items.iter_mut().enumerate().skip(1) {
+				*item = match item.clone() {
+					LIST(ref clause@CONS(..)) if i > 1	=> {
+						let clause = clause.get_items();
+						let names = clause.get(1).map_or(vec![], bound_names);
+						expand_binding(clause, 2, names, bound, env)?
+					},
+					e if i == 1							=> expand_in(&e, bound, env)?,
+					e									=> e
+				};
+			}
+			Ok(LIST(List::from_vec(items)))
+		},
+		Some(FUNCTION(SPECIAL(LAMBDA_FUNC))) if items.len() > 1 => {
+			let names = bound_names(&items[1]);
+			expand_binding(items, 2, names, bound, env)
+		},
+		Some(FUNCTION(SPECIAL(DEFUN))) if items.len() > 2 => {
+			let names = bound_names(&items[2]);
+			expand_binding(items, 3, names, bound, env)
+		},
+		Some(FUNCTION(SPECIAL(LET))) | Some(FUNCTION(SPECIAL(LET_STAR))) if items.len() > 1 => {
+			//each value of a let* sees the names bound before it
+			let sequential = head == Some(FUNCTION(SPECIAL(LET_STAR)));
+			let depth = bound.len();
+			let mut items = items;
+			if let LIST(ref bindings@CONS(..)) = items[1].clone() {
+				let mut expanded = vec![];
+				for binding in bindings.get_items() {
+					let binding = match binding {
+						LIST(ref pair@CONS(..)) => {
+							let mut pair = pair.get_items();
+							for value in pair.iter_mut().skip(1) {
+								let res = expand_in(value, bound, env);
+								if let Err(e) = res {
+									bound.truncate(depth);
+									return Err(e);
+								}
+								*value = res.ok().unwrap();
+							}
+							LIST(List::from_vec(pair))
+						},
+						e => e
+					};
+					if sequential {
+						bound.extend(binding_name(&binding));
 					}
+					expanded.push(binding);
 				}
-			} 
-		}
+				items[1] = LIST(List::from_vec(expanded));
+			}
+			bound.truncate(depth);
+			let names = bound_names(&items[1]);
+			expand_binding(items, 2, names, bound, env)
+		},
+		_ => expand_from(items, 0, bound, env)
+	}
+}
 
-		if is_key {
-			key_params.push((key_name.to_string(), args[i].clone()));
-			is_key = false;
-		} else if optional_params.len() != func.optn.len() {
-			optional_params.push(args[i].clone());
-		} else if func.rest != None {
-			rest_params.push(args[i].clone());
+//Expands every item of a form starting at the given index
+fn expand_from(mut items: Vec<Datum>, start: usize, bound: &mut Vec<Symbol>, env: &mut Env) -> Result<Datum, LispError> {
+	for item in items.iter_mut().skip(start) {
+		*item = expand_in(item, bound, env)?;
+	}
+	Ok(LIST(List::from_vec(items)))
+}
+
+//Expands the forms inside the commas of a backquoted template
+fn expand_unquoted(template: &Datum, bound: &mut Vec<Symbol>, env: &mut Env) -> Result<Datum, LispError> {
+	if let LIST(ref lst@CONS(..)) = *template {
+		if is_unquote(template) {
+			expand_from(lst.get_items(), 1, bound, env)
 		} else {
-			return Err(INVALID_NUMBER_OF_ARGS(args.len(), func.args.len()));
+			let mut items = lst.get_items();
+			for item in items.iter_mut() {
+				*item = expand_unquoted(item, bound, env)?;
+			}
+			Ok(LIST(List::from_vec(items)))
 		}
+	} else {
+		Ok(template.clone())
+	}
+}
+
+//Binds the macro's parameters to its unevaluated arguments and evaluates its
+//body, which returns the form the macro call expands into
+fn macroexpand_helper(func: &Lambda, args: Vec<Datum>, env: &mut Env) -> Result<Datum, LispError> {
+	let binds = match_args(func, args, env, false);
+	if let Err(e) = binds {
+		Err(e)
+	} else {
+		let binds = binds.ok().unwrap();
+		let name = ATOM(SYMBOL(func.name.unwrap_or_else(|| Symbol::intern("MACRO"))));
+		let call = Box::new(Call{name, args: binds.values(), form: None});
+		run(Ok(CALL(func.clone(), binds, call)), env)
 	}
+}
+
+#[cfg(test)]
+mod tests {
+	use Interpreter;
+	use testing::*;
 
-	let mut ret: Datum = *func.body.clone();
-	for (param, arg) in params.into_iter().zip(&func.args) {
-		ret = sublis(&ATOM(SYMBOL(arg.clone())), &param, ret);
+	#[test]
+	fn let_bindings_do_not_leak_through_tail_calls() {
+		let mut lisp = Interpreter::new();
+		run(&mut lisp, "(defun f [y] y)");
+		assert_eq!(run(&mut lisp, "(let ((zz 1)) (f 2))"), int(2));
+		assert!(lisp.eval_str("zz").is_err());
+		assert!(lisp.eval_str("(+ (let ((x 1)) (f x)) x)").is_err());
 	}
-	for (name, default) in func.optn.clone()[optional_params.len()..].to_vec() {
-		ret = sublis(&ATOM(SYMBOL(name)), &default, ret);
+	#[test]
+	fn range_and_make_list_keep_their_signatures() {
+		let mut lisp = Interpreter::new();
+		assert_eq!(run(&mut lisp, "(length (range 20000))"), int(20000));
+		assert_eq!(run(&mut lisp, "(length (make-list 'a 20000))"), int(20000));
+		assert_eq!(run(&mut lisp, "(range 10 :step 3 :min 1)"), run(&mut lisp, "'(1 4 7)"));
+		assert!(lisp.eval_str("(make-list 'a 2 '(b))").is_err());
 	}
-	for (param, arg) in optional_params.into_iter().zip(&func.optn) {
-		ret = sublis(&ATOM(SYMBOL(arg.0.clone())), &param, ret);
+	#[test]
+	fn local_variables_shadow_macros() {
+		let mut lisp = Interpreter::new();
+		run(&mut lisp, "(defmacro twice [x] `(* 2 ,x))");
+		assert_eq!(run(&mut lisp, "(let ((twice (lambda [n] (+ n 100)))) (twice 1))"), int(101));
+		assert_eq!(run(&mut lisp, "(let* ((a (twice 1)) (twice 1+)) (twice a))"), int(3));
+		run(&mut lisp, "(defun g [twice] (twice 5))");
+		assert_eq!(run(&mut lisp, "(g 1+)"), int(6));
+		assert_eq!(run(&mut lisp, "((lambda [&optional (twice 1+)] (twice 1)))"), int(2));
+		assert_eq!(run(&mut lisp, "(let ((y 1)) (twice y))"), int(2));
 	}
-	for (name, default) in 
-		func.key.clone().into_iter().filter(|&(ref n,_)| 
-			!key_params.clone().into_iter().map(|x| x.0)
-					   .collect::<Vec<_>>().contains(&n)) {
-		ret = sublis(&ATOM(SYMBOL(name)), &default, ret);
+	#[test]
+	fn hygiene_leaves_spliced_binders_alone() {
+		let mut lisp = Interpreter::new();
+		run(&mut lisp, "(defmacro-hygienic my-or (a b) `(let ((tmp ,a)) (if tmp tmp ,b)))");
+		assert_eq!(run(&mut lisp, "(let ((tmp 5)) (my-or nil tmp))"), int(5));
+		run(&mut lisp, "(defmacro-hygienic with-binds (binds body) `(let (,@binds) ,body))");
+		assert_eq!(run(&mut lisp, "(with-binds ((x 1) (y 2)) (+ x y))"), int(3));
+		run(&mut lisp, "(defmacro-hygienic fn (args body) `(lambda ,args ,body))");
+		assert_eq!(run(&mut lisp, "((fn (comma q) (+ comma q)) 1 2)"), int(3));
+		run(&mut lisp, "(defmacro-hygienic fn1 (arg body) `(lambda (,arg) ,body))");
+		assert_eq!(run(&mut lisp, "((fn1 x (* x 10)) 4)"), int(40));
 	}
-	for (name, val) in key_params {
-		ret = sublis(&ATOM(SYMBOL(name)), &val, ret);
+	#[test]
+	fn hygiene_leaves_quoted_symbols_alone() {
+		let mut lisp = Interpreter::new();
+		run(&mut lisp, "(defmacro-hygienic m3 (x) `(let ((q ,x)) 'q))");
+		assert_eq!(run(&mut lisp, "(m3 1)"), sym("Q"));
+		run(&mut lisp, "(defmacro-hygienic m2 (x) `(let ((q 1)) (list q ',x 'q)))");
+		assert_eq!(run(&mut lisp, "(m2 q)"), run(&mut lisp, "'(1 q q)"));
 	}
-	if let Some(name) = func.rest.clone() {
-		ret = sublis(&ATOM(SYMBOL(name)), &LIST(List::from_vec(rest_params)), ret);
+	#[test]
+	fn backquote_leaves_quoted_forms_unexpanded() {
+		let mut lisp = Interpreter::new();
+		run(&mut lisp, "(defmacro two () 2)");
+		run(&mut lisp, "(defun f () `(a '(two) ,(two)))");
+		assert_eq!(run(&mut lisp, "(f)"), run(&mut lisp, "'(a '(two) 2)"));
 	}
-	Ok(ret)
-}
\ No newline at end of file
+	#[test]
+	fn gensyms_are_not_keywords() {
+		use types::Datum::*;
+		use types::Atom::*;
+		let mut lisp = Interpreter::new();
+		run(&mut lisp, "(defmacro-hygienic call-with (f) `(let ((v 7)) (,f v)))");
+		assert_eq!(run(&mut lisp, "(call-with (lambda (a) (* a 2)))"), int(14));
+		match run(&mut lisp, "(gensym)") {
+			ATOM(SYMBOL(s))	=> assert!(!s.name().starts_with(':')),
+			e				=> panic!("gensym returned {}", e)
+		}
+	}
+	#[test]
+	fn equal_terminates_on_circular_lists() {
+		let mut lisp = Interpreter::new();
+		run(&mut lisp, "(defun ring (n) (let ((cells (list n 2 3))) (progn (set-cdr! (cdr (cdr cells)) cells) cells)))");
+		assert_eq!(run(&mut lisp, "(equal? (ring 1) (ring 1))"), run(&mut lisp, "t"));
+		assert_eq!(run(&mut lisp, "(equal? (ring 1) (ring 2))"), run(&mut lisp, "nil"));
+	}
+	#[test]
+	fn case_matches_keys_with_equal() {
+		let mut lisp = Interpreter::new();
+		run(&mut lisp, "(defun kind (x) (case x (1 'one) ((2 \"two\" (3)) 'listed) (otherwise 'other)))");
+		assert_eq!(run(&mut lisp, "(kind 1)"), sym("ONE"));
+		assert_eq!(run(&mut lisp, "(kind 1.0)"), sym("ONE"));
+		assert_eq!(run(&mut lisp, "(kind 2/2)"), sym("ONE"));
+		assert_eq!(run(&mut lisp, "(kind \"two\")"), sym("LISTED"));
+		assert_eq!(run(&mut lisp, "(kind (list 3))"), sym("LISTED"));
+		assert_eq!(run(&mut lisp, "(kind 'one)"), sym("OTHER"));
+		assert_eq!(run(&mut lisp, "(case 5 (1 'one))"), run(&mut lisp, "nil"));
+		assert_eq!(run(&mut lisp, "(case (+ 1 1) (2 'evaluated 'last) (t 'other))"), sym("LAST"));
+	}
+	#[test]
+	fn conditionals_short_circuit() {
+		let mut lisp = Interpreter::new();
+		assert_eq!(run(&mut lisp, "(and 1 nil (car 5))"), run(&mut lisp, "nil"));
+		assert_eq!(run(&mut lisp, "(or nil 2 (car 5))"), int(2));
+		assert_eq!(run(&mut lisp, "(cond (nil (car 5)) ((+ 1 2)))"), int(3));
+		assert_eq!(run(&mut lisp, "(when nil (car 5))"), run(&mut lisp, "nil"));
+		assert_eq!(run(&mut lisp, "(unless nil 1 2)"), int(2));
+	}
+	#[test]
+	fn functions_know_their_names_and_docstrings() {
+		let mut lisp = Interpreter::new();
+		run(&mut lisp, "(defun sq (x) \"Squares x.\" (* x x))");
+		assert_eq!(run(&mut lisp, "(documentation 'sq)"), run(&mut lisp, "\"Squares x.\""));
+		assert_eq!(run(&mut lisp, "(documentation (lambda (x) x))"), run(&mut lisp, "nil"));
+		assert_eq!(format!("{}", run(&mut lisp, "(let ((f sq)) f)")), "Lambda SQ{args: [\"X\"], body: (* X X)}");
+		let describe = |lisp: &mut Interpreter, source: &str| super::description(&[run(lisp, source)], lisp.env()).unwrap();
+		assert_eq!(describe(&mut lisp, "'sq"), "SQ is a function\nLambda list: (X)\n\nSquares x.");
+		assert_eq!(describe(&mut lisp, "(lambda (a &optional b) a)"), "An anonymous function\nLambda list: (A &OPTIONAL B)");
+		assert_eq!(describe(&mut lisp, "5"), "5 is of type INTEGER");
+		run(&mut lisp, "(define answer 42)");
+		assert_eq!(describe(&mut lisp, "'answer"), "ANSWER is a variable of type INTEGER with value 42");
+		assert!(super::description(&[sym("NO-SUCH-NAME")], lisp.env()).is_err());
+	}
+	#[test]
+	fn help_shows_signatures_and_apropos_finds_names() {
+		let mut lisp = Interpreter::new();
+		assert_eq!(super::description(&[sym("CAR")], lisp.env()).unwrap(),
+				   "CAR is a native function\nSignature: (car list)\nTakes 1 argument\n\nReturns the first element of a list.");
+		assert!(super::description(&[sym("IF")], lisp.env()).unwrap().starts_with("IF is a special form\nSignature: (if "));
+		run(&mut lisp, "(defun string-shout (s) (string-upcase s))");
+		let found = super::matching_names(&[run(&mut lisp, "\"string-s\"")], lisp.env()).unwrap();
+		assert_eq!(found, vec![format!("{:<24} {}", "STRING-SHOUT", "function (S)"),
+							   format!("{:<24} {}", "STRING-SPLIT", "(string-split string separator)")]);
+		assert!(super::matching_names(&[int(1)], lisp.env()).is_err());
+		assert_eq!(run(&mut lisp, "(help)"), run(&mut lisp, "nil"));
+	}
+	#[test]
+	fn equality_on_bignums_is_exact() {
+		let mut lisp = Interpreter::new();
+		assert_eq!(run(&mut lisp, "(= 18446744073709551616 18446744073709551617)"), run(&mut lisp, "nil"));
+		assert_eq!(run(&mut lisp, "(equal? 18446744073709551616 18446744073709551617)"), run(&mut lisp, "nil"));
+		assert_eq!(run(&mut lisp, "(= 18446744073709551616 18446744073709551616 36893488147419103232/2)"), run(&mut lisp, "t"));
+		assert_eq!(run(&mut lisp, "(equal? 1 1.0)"), run(&mut lisp, "t"));
+	}
+}
@@ -0,0 +1,111 @@
+extern crate time;
+extern crate rand;
+extern crate term_painter;
+extern crate num;
+
+pub mod native;
+pub mod parser;
+pub mod errors;
+pub mod types;
+pub mod eval;
+pub mod env;
+pub mod doc;
+
+#[cfg(test)]
+mod testing;
+
+use errors::LispError;
+use types::Datum;
+use eval::eval_source;
+use env::Env;
+
+//Compiled in so that embedding programs don't need std.rlisp on disk
+const STD: &str = include_str!("std.rlisp");
+
+//RLisp embedded in another program, e.g. as a configuration language:
+//
+//	let mut lisp = Interpreter::new();
+//	lisp.register("double", |args, _| match args.first() { ... })?;
+//	lisp.eval_str("(double 21)")
+pub struct Interpreter {
+	env: Env
+}
+
+impl Interpreter {
+	//Starts with the builtins and the standard library, like the REPL. The
+	//library is compiled in, so failing to load it is a bug in RLisp itself.
+	pub fn new() -> Interpreter {
+		let mut env = Env::new();
+		if let Err(e) = eval_source(STD, "std.rlisp", false, &mut env) {
+			panic!("the standard library failed to load: {}", e.message());
+		}
+		env.push();
+		Interpreter{env}
+	}
+
+	//Evaluates every form in the source and returns the value of the last one
+	pub fn eval_str(&mut self, source: &str) -> Result<Datum, LispError> {
+		let preserve_case = self.env.preserve_case();
+		eval_source(source, "<embedded>", preserve_case, &mut self.env)
+	}
+
+	//Makes a Rust function callable from Lisp under the given name. It gets the
+	//evaluated arguments and the environment it was called in. Fails with
+	//OVERRIDE_RESERVED if the name belongs to a builtin.
+	pub fn register<F>(&mut self, name: &str, func: F) -> Result<(), LispError>
+		where F: Fn(&[Datum], &mut Env) -> Result<Datum, LispError> + 'static {
+		self.env.register(name, func)
+	}
+
+	pub fn env(&mut self) -> &mut Env {
+		&mut self.env
+	}
+}
+
+impl Default for Interpreter {
+	fn default() -> Interpreter {
+		Interpreter::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use types::Datum::*;
+	use types::Atom::*;
+	use types::Number::*;
+	use errors::LispError::*;
+	use testing::*;
+
+	fn double(args: &[Datum], _: &mut Env) -> Result<Datum, LispError> {
+		match args.first() {
+			Some(&ATOM(NUMBER(INTEGER(n))))	=> Ok(int(2 * n)),
+			Some(e)							=> Err(INVALID_ARGUMENT_TYPE(Box::new(e.clone()), "integer")),
+			None							=> Err(INVALID_NUMBER_OF_ARGS(0, 1))
+		}
+	}
+
+	#[test]
+	fn registered_functions_are_callable() {
+		let mut lisp = Interpreter::new();
+		assert_eq!(lisp.register("double", double), Ok(()));
+		assert_eq!(lisp.eval_str("(double 21)"), Ok(int(42)));
+		assert_eq!(lisp.eval_str("(apply + (map double '(1 2 3)))"), Ok(int(12)));
+		assert!(lisp.eval_str("(defun double [x] x)").is_err());
+	}
+
+	#[test]
+	fn registering_builtins_is_refused() {
+		let mut lisp = Interpreter::new();
+		assert_eq!(lisp.register("car", double), Err(OVERRIDE_RESERVED("CAR".to_string())));
+		assert_eq!(lisp.register("if", double), Err(OVERRIDE_RESERVED("IF".to_string())));
+		assert_eq!(lisp.eval_str("(car '(1 2))"), Ok(int(1)));
+	}
+
+	#[test]
+	fn registering_replaces_standard_library_functions() {
+		let mut lisp = Interpreter::new();
+		assert_eq!(lisp.register("length", |_, _| Ok(int(-1))), Ok(()));
+		assert_eq!(lisp.eval_str("(length '(1 2))"), Ok(int(-1)));
+	}
+}
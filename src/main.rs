@@ -1,21 +1,14 @@
-extern crate time;
 extern crate rand;
 extern crate term_painter;
+extern crate rlisp;
 
-mod native;
-mod parser;
-mod errors;
-mod types;
-mod eval;
-mod env;
+use rlisp::parser::*;
+use rlisp::errors::*;
+use rlisp::types::*;
+use rlisp::eval::*;
+use rlisp::Interpreter;
 
-use parser::*;
-use errors::*;
-use types::*;
-use eval::*;
-use env::*;
-
-use errors::LispError::*;
+use rlisp::errors::LispError::*;
 
 use term_painter::ToStyle;
 use term_painter::Color::*;
@@ -26,37 +19,49 @@ use std::io::prelude::*;
 
 fn levenshtein(s1: &String, s2: &String, sofar: usize, cap: usize) -> usize {
 	if s1 == s2 {
-		return sofar;
-	} else if s1.len() == 0 || s2.len() == 0 {
-		return sofar+s1.len()+s2.len();
+		sofar
+	} else if s1.is_empty() || s2.is_empty() {
+		sofar+s1.len()+s2.len()
 	} else if sofar >= cap {
 		cap
 	} else {
-		let cost = if s1.chars().next() == s2.chars().next() {0} else {1};
-		vec![levenshtein(&s1[1..].to_string(), &s2, 1+sofar, cap),
-		   	 levenshtein(&s1, &s2[1..].to_string(), 1+sofar, cap),
-		   	 levenshtein(&s1[1..].to_string(), &s2[1..].to_string(),
-		   	 			 sofar+cost,cap)]
+		let (c1, c2) = (s1.chars().next().unwrap(), s2.chars().next().unwrap());
+		let cost = if c1 == c2 {0} else {1};
+		//names can hold any character, so drop the first one by its encoded length
+		let (rest1, rest2) = (s1[c1.len_utf8()..].to_string(), s2[c2.len_utf8()..].to_string());
+		vec![levenshtein(&rest1, s2, 1+sofar, cap),
+		   	 levenshtein(s1, &rest2, 1+sofar, cap),
+		   	 levenshtein(&rest1, &rest2, sofar+cost,cap)]
 			.into_iter().min().unwrap()
 	}
 }
 
-fn matched_parentheses(s: &String) -> Option<bool> {
+//Uses the tokenizer so that brackets inside strings and comments are ignored.
+//Returns Some(false) when the input is incomplete and more lines are needed.
+fn matched_parentheses(s: &str) -> Option<bool> {
+	let tkns = match tokenize(s, "<repl>", false) {
+		Ok(tkns)	=> tkns,
+		Err(LOCATED(_, ref e)) if **e == UNTERMINATED_STRING || **e == UNTERMINATED_COMMENT
+					=> return Some(false),
+		Err(_)		=> return Some(true) //reported when the input is parsed
+	};
+
 	let mut stack: Vec<char> = vec![];
-	let mut in_string = false;
-	for c in s.chars() {
-		if (c=='(' || c=='[') && !in_string {
-			stack.push(c)
-		} else if c==')' && !in_string {
-			if stack.pop().unwrap_or(' ') != '(' {
-				return None
-			}
-		} else if c==']' && !in_string {
-			if stack.pop().unwrap_or(' ') != '[' {
-				return None
+	for tkn in &tkns {
+		if let Atom::SYMBOL(ref sym) = tkn.atom {
+			let c = sym.name().chars().next().unwrap_or(' ');
+			if *sym == "#(" || *sym == "#H(" || *sym == "#S(" {
+				stack.push('(')
+			} else if sym.name().len() != 1 {
+				continue
+			} else if c=='(' || c=='[' {
+				stack.push(c)
+			} else if c==')' || c==']' {
+				let open = if c==')' {'('} else {'['};
+				if stack.pop() != Some(open) {
+					return None
+				}
 			}
-		} else if c=='"' {
-			in_string = !in_string
 		}
 	}
 	Some(stack.is_empty())
@@ -65,29 +70,25 @@ fn matched_parentheses(s: &String) -> Option<bool> {
 fn main() {
 	let mut input = String::new();
 	let mut result: Result<Datum, LispError>;
-	let mut env = Env::new();
-
-	eval(&parse(&mut tokenize(&"(load \"std.rlisp\")".to_string())),
-		 &mut env).ok();
-	env.push();
+	let mut lisp = Interpreter::new();
+	let env = lisp.env();
 	loop {
 		input.clear();
 		result = Ok(Datum::LIST(List::NIL));
 
 		print!("{}", BrightCyan.paint("RLisp>> ")); 
-		io::stdout().flush().ok().expect("Could not flush stdout");
-		io::stdin().read_line(&mut input).ok().expect("Failed to read line");
+		io::stdout().flush().expect("Could not flush stdout");
+		io::stdin().read_line(&mut input).expect("Failed to read line");
 		loop {
 			match matched_parentheses(&input) {
 				Some(finished) 	=> {
 					if !finished {
-						print!("\t"); io::stdout().flush().ok().expect("Could not flush stdout");
+						print!("\t"); io::stdout().flush().expect("Could not flush stdout");
 
 						let mut next_line = String::new();
-						io::stdin().read_line(&mut next_line).ok()
-								   .expect("Failed to read line");
-						input = format!("{}\n\t{}", input, next_line);
-					} else if input.trim() == "".to_string() {
+						io::stdin().read_line(&mut next_line).expect("Failed to read line");
+						input.push_str(&next_line);
+					} else if input.trim() == "" {
 						result = Err(NO_INPUT); break
 					} else {break}
 				},
@@ -96,16 +97,19 @@ fn main() {
 		}
 		if result != Err(MISMATCHED_BRACKETS) &&
 		   result != Err(NO_INPUT) {
-			result = eval(&parse(&mut tokenize(&input)), &mut env)
+			result = tokenize(&input, "<repl>", env.preserve_case()).and_then(|mut tkns| parse(&mut tkns));
+			if let Ok(form) = result {
+				result = eval(&form, env);
+			}
 		} match result {
-			Ok(ref a) 	=> {println!("{}", BrightYellow.paint(a.clone()));env.set("%%%".to_string(), a.clone());},
+			Ok(ref a) 	=> {println!("{}", BrightYellow.paint(a.clone()));env.set(Symbol::intern("%%%"), a.clone());},
 			Err(ref a)	=> println!("{}", Blue.paint(a.message()))
-		} if let Err(UNBOUND_VARIABLE(name)) = result.clone() {
+		} if let Err(UNBOUND_VARIABLE(name)) = result.clone().map_err(|e| e.root().clone()) {
 			let mut min = ("".to_string(), 99999);
 			for (key, _) in env.join() {
-				let score = levenshtein(&key, &name, 0, 3);
+				let score = levenshtein(&key.name().to_string(), &name, 0, 3);
 				if score < min.1 || (score == min.1 && rand::thread_rng().gen_range(0,2) == 1) {
-					min = (key, score);
+					min = (key.name().to_string(), score);
 				}
 			}
 			if min.1 <= 2 {
@@ -114,14 +118,42 @@ fn main() {
 				println!("Did you mean '(QUOTE {})'?", name);
 			}*/
 		}
-		println!("");
+		println!();
 		
 		/*
 		println!("Development output:");
-		println!("	tokens: {:?}\n", tokenize(&input));
-		println!("	parsed expression: {}\n", parse(&mut tokenize(&input)));
+		println!("	tokens: {:?}\n", tokenize(&input, "<repl>", env.preserve_case()));
+		println!("	parsed expression: {:?}\n", tokenize(&input, "<repl>", env.preserve_case()).and_then(|mut tkns| parse(&mut tkns)));
 		println!("	debug result: {:?}\n", result);
 		println!("\n");
 		*/
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn brackets_match_outside_strings_and_comments() {
+		assert_eq!(matched_parentheses("(a [b] (c))"), Some(true));
+		assert_eq!(matched_parentheses("(a (b)"), Some(false));
+		assert_eq!(matched_parentheses("(a ]"), None);
+		assert_eq!(matched_parentheses("(a \")\")"), Some(true));
+		assert_eq!(matched_parentheses("(a \"(b"), Some(false));
+		assert_eq!(matched_parentheses("(a ; )\n"), Some(false));
+		assert_eq!(matched_parentheses("(a #| ) |# b)"), Some(true));
+		assert_eq!(matched_parentheses("#| (a"), Some(false));
+		assert_eq!(matched_parentheses("#(1 #H((a 1)) #\\( #\\))"), Some(true));
+	}
+	#[test]
+	fn suggestions_measure_edit_distance_up_to_a_cap() {
+		let dist = |a: &str, b: &str| levenshtein(&a.to_string(), &b.to_string(), 0, 3);
+		assert_eq!(dist("CAR", "CAR"), 0);
+		assert_eq!(dist("CAR", "CDR"), 1);
+		assert_eq!(dist("LENGTH", "LENGHT"), 2);
+		assert_eq!(dist("STRING-SPLIT", "SPLIT"), 3);
+		assert_eq!(dist("", "AB"), 2);
+		assert_eq!(dist("\u{3bb}X", "X"), 1);
+	}
+}
@@ -13,42 +13,54 @@ use types::Atom::*;
 
 use rand::Rng;
 
+use num::{BigInt, Signed};
+
 use term_painter::ToStyle;
 use term_painter::Color::*;
 
 use std::fmt;
+use std::rc::Rc;
+use std::cell::RefCell;
+
+//Integer and rational arithmetic promotes on overflow, but reals can only
+//overflow to infinity, which is reported as an error instead
+fn checked_op(a: Number, b: Number, op: fn(Number, Number) -> Number) -> Result<Number, LispError> {
+	let finite = a.is_finite() && b.is_finite();
+	let res = op(a, b);
+	if finite && !res.is_finite() {
+		Err(ARITHMETIC_OVERFLOW)
+	} else {
+		Ok(res)
+	}
+}
 
 pub fn add(args: Vec<Datum>) -> Result<Datum, LispError> {
 	let mut sum = INTEGER(0);
 	for item in args {
 		match item {
-			ATOM(NUMBER(n))	=> {sum = sum + n},
-			_				=> return Err(INVALID_ARGUMENT_TYPE(item, "number"))
+			ATOM(NUMBER(n))	=> match checked_op(sum, n, |a,b| a+b) {
+				Ok(res)	=> sum = res,
+				Err(e)	=> return Err(e)
+			},
+			_				=> return Err(INVALID_ARGUMENT_TYPE(Box::new(item), "number"))
 		}
 	}
 	Ok(ATOM(NUMBER(sum.simplify())))
 }
 
 pub fn sub(args: Vec<Datum>) -> Result<Datum, LispError> {
-	if args.len() == 0 {
-		Err(INVALID_NUMBER_OF_ARGS(0,1))
-	} else {
-		match args[0] {
-			ATOM(NUMBER(n))	=> {
-				if args.len() == 1 {
-					return Ok(ATOM(NUMBER(-n)))
-				}
-				let res = add(args[1..].to_vec());
-				if res.is_err() {
-					return res;
-				}
-				match res.ok().unwrap() {
-					ATOM(NUMBER(m))	=> Ok(ATOM(NUMBER((n-m).simplify()))),
-					ref e @ _		=> Err(INVALID_ARGUMENT_TYPE(e.clone(), "number"))
-				}
-			},
-			_				=> Err(INVALID_ARGUMENT_TYPE(args[0].clone(), "number"))
-		}
+	match args[0] {
+		ATOM(NUMBER(ref n))	=> {
+			if args.len() == 1 {
+				return Ok(ATOM(NUMBER(-n.clone())))
+			}
+			match add(args[1..].to_vec())? {
+				ATOM(NUMBER(m))	=> checked_op(n.clone(), m, |a,b| a-b)
+										.map(|res| ATOM(NUMBER(res.simplify()))),
+				ref e			=> Err(INVALID_ARGUMENT_TYPE(Box::new(e.clone()), "number"))
+			}
+		},
+		_				=> Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "number"))
 	}
 }
 
@@ -56,34 +68,35 @@ pub fn mul(args: Vec<Datum>) -> Result<Datum, LispError> {
 	let mut prd = INTEGER(1);
 	for item in args {
 		match item {
-			ATOM(NUMBER(n))	=> {prd = prd * n},
-			_				=> return Err(INVALID_ARGUMENT_TYPE(item, "number"))
+			ATOM(NUMBER(n))	=> match checked_op(prd, n, |a,b| a*b) {
+				Ok(res)	=> prd = res,
+				Err(e)	=> return Err(e)
+			},
+			_				=> return Err(INVALID_ARGUMENT_TYPE(Box::new(item), "number"))
 		}
 	}
 	Ok(ATOM(NUMBER(prd.simplify())))
 }
 
 pub fn div(args: Vec<Datum>) -> Result<Datum, LispError> {
-	if args.len() == 0 {
-		Err(INVALID_NUMBER_OF_ARGS(0,1))
-	} else {
-		match args[0] {
-			ATOM(NUMBER(n))	=> {
-				if args.len() == 1 {
-					return Ok(ATOM(NUMBER(INTEGER(1)/n)))
-				}
-				let res = mul(args[1..].to_vec());
-				if res.is_err() {
-					return res;
-				}
-				match res.ok().unwrap() {
-					ATOM(NUMBER(m)) if m.val()==0. => Err(DIVISION_BY_ZERO),
-					ATOM(NUMBER(m))	=> Ok(ATOM(NUMBER((n/m).simplify()))),
-					ref e @ _		=> Err(INVALID_ARGUMENT_TYPE(e.clone(), "number"))
+	match args[0] {
+		ATOM(NUMBER(ref n))	=> {
+			if args.len() == 1 {
+				return if n.is_zero() {
+					Err(DIVISION_BY_ZERO)
+				} else {
+					checked_op(INTEGER(1), n.clone(), |a,b| a/b)
+						.map(|res| ATOM(NUMBER(res.simplify())))
 				}
-			},
-			_				=> Err(INVALID_ARGUMENT_TYPE(args[0].clone(), "number"))
-		}
+			}
+			match mul(args[1..].to_vec())? {
+				ATOM(NUMBER(m)) if m.is_zero() => Err(DIVISION_BY_ZERO),
+				ATOM(NUMBER(m))	=> checked_op(n.clone(), m, |a,b| a/b)
+										.map(|res| ATOM(NUMBER(res.simplify()))),
+				ref e			=> Err(INVALID_ARGUMENT_TYPE(Box::new(e.clone()), "number"))
+			}
+		},
+		_				=> Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "number"))
 	}
 }
 
@@ -92,86 +105,80 @@ pub fn list(args: Vec<Datum>) -> Result<Datum, LispError> {
 }
 
 pub fn cons(args: Vec<Datum>) -> Result<Datum, LispError> {
-	match args.len() {
-		2 		=> Ok(LIST(CONS(Box::new(args[0].clone()),
-								Box::new(args[1].clone())))),
-		e @ _	=> Err(INVALID_NUMBER_OF_ARGS(e, 2))
-	}
+	Ok(LIST(List::cons(args[0].clone(), args[1].clone())))
 }
 
 pub fn car(args: Vec<Datum>) -> Result<Datum, LispError> {
-	match args.len() {
-		1 => {
-			match args[0] {
-				LIST(ref l)	=> Ok(l.car()),
-				_			=> Err(INVALID_ARGUMENT_TYPE(args[0].clone(), "list"))
-			}
-		},
-		e @ _ => Err(INVALID_NUMBER_OF_ARGS(e, 1))
+	match args[0] {
+		LIST(ref l)	=> Ok(l.car()),
+		_			=> Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "list"))
 	}
 }
 
 pub fn cdr(args: Vec<Datum>) -> Result<Datum, LispError> {
-	match args.len() {
-		1 => {
-			match args[0] {
-				LIST(ref l)	=> Ok(l.cdr()),
-				_			=> Err(INVALID_ARGUMENT_TYPE(args[0].clone(), "list"))
-			}
-		},
-		e @ _ => Err(INVALID_NUMBER_OF_ARGS(e, 1))
-	}	
+	match args[0] {
+		LIST(ref l)	=> Ok(l.cdr()),
+		_			=> Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "list"))
+	}
 }
 
 pub fn nth(args: Vec<Datum>) -> Result<Datum, LispError> {
-	match args.len() {
-		2 => {
-			if let ATOM(NUMBER(INTEGER(n))) = args[0] {
-				let n = n as usize;
-				match args[1] {
-					LIST(ref l)	=> return if l.get_items().len() > n {
-						Ok(l.get_items()[n].clone())
-					} else {Ok(LIST(NIL))},
-					_		=> return Err(INVALID_ARGUMENT_TYPE(args[1].clone(), "list"))
-				}
-			}
-			Err(INVALID_ARGUMENT_TYPE(args[0].clone(), "integer"))
+	if let ATOM(NUMBER(INTEGER(n))) = args[0] {
+		let n = n as usize;
+		match args[1] {
+			LIST(ref l)	=> return match l.nth_cdr(n) {
+				LIST(rest)	=> Ok(rest.car()),
+				e			=> Ok(e)
+			},
+			_		=> return Err(INVALID_ARGUMENT_TYPE(Box::new(args[1].clone()), "list"))
+		}
+	}
+	Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "integer"))
+}
+
+pub fn set_car(args: Vec<Datum>) -> Result<Datum, LispError> {
+	match args[0] {
+		LIST(CONS(ref c, _))	=> {
+			*c.borrow_mut() = args[1].clone();
+			Ok(args[1].clone())
 		},
-		e @ _ => Err(INVALID_NUMBER_OF_ARGS(e, 2))
+		_						=> Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "cons"))
 	}
 }
 
-pub fn nth_cdr(args: Vec<Datum>) -> Result<Datum, LispError> {
-	match args.len() {
-		2 => {
-			if let ATOM(NUMBER(INTEGER(n))) = args[0] {
-				let n = n as usize;
-				match args[1] {
-					LIST(ref l)	=> return if l.get_items().len() > n {
-						Ok(LIST(List::from_vec(
-							l.get_items().into_iter().skip(n).collect())))
-					} else {Ok(LIST(NIL))},
-					_		=> return Err(INVALID_ARGUMENT_TYPE(args[1].clone(), "list"))
-				}
-			}
-			Err(INVALID_ARGUMENT_TYPE(args[0].clone(), "integer"))
+pub fn set_cdr(args: Vec<Datum>) -> Result<Datum, LispError> {
+	match args[0] {
+		LIST(CONS(_, ref c))	=> {
+			*c.borrow_mut() = args[1].clone();
+			Ok(args[1].clone())
 		},
-		e @ _ => Err(INVALID_NUMBER_OF_ARGS(e, 2))
+		_						=> Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "cons"))
+	}
+}
+
+pub fn nth_cdr(args: Vec<Datum>) -> Result<Datum, LispError> {
+	if let ATOM(NUMBER(INTEGER(n))) = args[0] {
+		let n = n as usize;
+		match args[1] {
+			LIST(ref l)	=> return Ok(l.nth_cdr(n)),
+			_		=> return Err(INVALID_ARGUMENT_TYPE(Box::new(args[1].clone()), "list"))
+		}
 	}
+	Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "integer"))
 }
 
 pub fn greater_than(args: Vec<Datum>) -> Result<Datum, LispError> {
 	for i in 1..args.len() {
-		if let ATOM(NUMBER(a)) = args[i-1] {
-			if let ATOM(NUMBER(b)) = args[i] {
+		if let ATOM(NUMBER(ref a)) = args[i-1] {
+			if let ATOM(NUMBER(ref b)) = args[i] {
 				if a <= b {
 					return Ok(LIST(NIL));
 				}
 			} else {
-				return Err(INVALID_ARGUMENT_TYPE(args[i].clone(), "number"));
+				return Err(INVALID_ARGUMENT_TYPE(Box::new(args[i].clone()), "number"));
 			}
 		} else {
-			return Err(INVALID_ARGUMENT_TYPE(args[i-1].clone(), "number"));
+			return Err(INVALID_ARGUMENT_TYPE(Box::new(args[i-1].clone()), "number"));
 		}
 	}
 	Ok(ATOM(T))
@@ -179,16 +186,16 @@ pub fn greater_than(args: Vec<Datum>) -> Result<Datum, LispError> {
 
 pub fn greater_equal(args: Vec<Datum>) -> Result<Datum, LispError> {
 	for i in 1..args.len() {
-		if let ATOM(NUMBER(a)) = args[i-1] {
-			if let ATOM(NUMBER(b)) = args[i] {
+		if let ATOM(NUMBER(ref a)) = args[i-1] {
+			if let ATOM(NUMBER(ref b)) = args[i] {
 				if a < b {
 					return Ok(LIST(NIL));
 				}
 			} else {
-				return Err(INVALID_ARGUMENT_TYPE(args[i].clone(), "number"));
+				return Err(INVALID_ARGUMENT_TYPE(Box::new(args[i].clone()), "number"));
 			}
 		} else {
-			return Err(INVALID_ARGUMENT_TYPE(args[i-1].clone(), "number"));
+			return Err(INVALID_ARGUMENT_TYPE(Box::new(args[i-1].clone()), "number"));
 		}
 	}
 	Ok(ATOM(T))
@@ -196,16 +203,16 @@ pub fn greater_equal(args: Vec<Datum>) -> Result<Datum, LispError> {
 
 pub fn less_than(args: Vec<Datum>) -> Result<Datum, LispError> {
 	for i in 1..args.len() {
-		if let ATOM(NUMBER(a)) = args[i-1] {
-			if let ATOM(NUMBER(b)) = args[i] {
+		if let ATOM(NUMBER(ref a)) = args[i-1] {
+			if let ATOM(NUMBER(ref b)) = args[i] {
 				if a >= b {
 					return Ok(LIST(NIL));
 				}
 			} else {
-				return Err(INVALID_ARGUMENT_TYPE(args[i].clone(), "number"));
+				return Err(INVALID_ARGUMENT_TYPE(Box::new(args[i].clone()), "number"));
 			}
 		} else {
-			return Err(INVALID_ARGUMENT_TYPE(args[i-1].clone(), "number"));
+			return Err(INVALID_ARGUMENT_TYPE(Box::new(args[i-1].clone()), "number"));
 		}
 	}
 	Ok(ATOM(T))
@@ -213,16 +220,16 @@ pub fn less_than(args: Vec<Datum>) -> Result<Datum, LispError> {
 
 pub fn less_equal(args: Vec<Datum>) -> Result<Datum, LispError> {
 	for i in 1..args.len() {
-		if let ATOM(NUMBER(a)) = args[i-1] {
-			if let ATOM(NUMBER(b)) = args[i] {
+		if let ATOM(NUMBER(ref a)) = args[i-1] {
+			if let ATOM(NUMBER(ref b)) = args[i] {
 				if a > b {
 					return Ok(LIST(NIL));
 				}
 			} else {
-				return Err(INVALID_ARGUMENT_TYPE(args[i].clone(), "number"));
+				return Err(INVALID_ARGUMENT_TYPE(Box::new(args[i].clone()), "number"));
 			}
 		} else {
-			return Err(INVALID_ARGUMENT_TYPE(args[i-1].clone(), "number"));
+			return Err(INVALID_ARGUMENT_TYPE(Box::new(args[i-1].clone()), "number"));
 		}
 	}
 	Ok(ATOM(T))
@@ -230,115 +237,114 @@ pub fn less_equal(args: Vec<Datum>) -> Result<Datum, LispError> {
 
 pub fn math_equal(args: Vec<Datum>) -> Result<Datum, LispError> {
 	for i in 1..args.len() {
-		if let ATOM(NUMBER(a)) = args[i-1] {
-			if let ATOM(NUMBER(b)) = args[i] {
-				if a.simplify() != b.simplify() && a.val() != b.val() {
+		if let ATOM(NUMBER(ref a)) = args[i-1] {
+			if let ATOM(NUMBER(ref b)) = args[i] {
+				if !a.same_value(b) {
 					return Ok(LIST(NIL));
 				}
 			} else {
-				return Err(INVALID_ARGUMENT_TYPE(args[i].clone(), "number"));
+				return Err(INVALID_ARGUMENT_TYPE(Box::new(args[i].clone()), "number"));
 			}
 		} else {
-			return Err(INVALID_ARGUMENT_TYPE(args[i-1].clone(), "number"));
+			return Err(INVALID_ARGUMENT_TYPE(Box::new(args[i-1].clone()), "number"));
 		}
 	}
 	Ok(ATOM(T))
 }
 
 pub fn lisp_mod(args: Vec<Datum>) -> Result<Datum, LispError> {
-	if args.len() != 2 {
-		Err(INVALID_NUMBER_OF_ARGS(args.len(), 2))
-	} else if let ATOM(NUMBER(a)) = args[0] {
-		if let ATOM(NUMBER(b)) = args[1] {
-			if b.val() == 0. {
+	if let ATOM(NUMBER(ref a)) = args[0] {
+		if let ATOM(NUMBER(ref b)) = args[1] {
+			if b.is_zero() {
 				Err(DIVISION_BY_ZERO)
 			} else {
-				Ok(ATOM(NUMBER(
-					a - b*INTEGER((a/b).val().floor() as i64))))
+				checked_op(a.clone(), b.clone(), |a,b| a.clone() - b.clone()*(a/b).floor())
+					.map(|res| ATOM(NUMBER(res.simplify())))
 			}
 		} else {
-			Err(INVALID_ARGUMENT_TYPE(args[1].clone(), "number"))
+			Err(INVALID_ARGUMENT_TYPE(Box::new(args[1].clone()), "number"))
 		}
 	} else {
-		Err(INVALID_ARGUMENT_TYPE(args[0].clone(), "number"))
+		Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "number"))
 	}
 }
 
 pub fn powi(args: Vec<Datum>) -> Result<Datum, LispError> {
-	if args.len() != 2 {
-		Err(INVALID_NUMBER_OF_ARGS(args.len(), 2))
-	} else if let ATOM(NUMBER(a)) = args[0] {
-		if let ATOM(NUMBER(INTEGER(b))) = args[1] {
-			Ok(ATOM(NUMBER(REAL(
-				a.val().powi(b as i32)).simplify())))
+	if let ATOM(NUMBER(ref a)) = args[0] {
+		let b = match args[1] {
+			ATOM(NUMBER(INTEGER(b)))		=> BigInt::from(b),
+			ATOM(NUMBER(BIGINT(ref b)))	=> b.clone(),
+			ref e						=> return Err(INVALID_ARGUMENT_TYPE(Box::new(e.clone()), "integer"))
+		};
+		if a.is_zero() && b.is_negative() {
+			Err(DIVISION_BY_ZERO)
 		} else {
-			Err(INVALID_ARGUMENT_TYPE(args[1].clone(), "integer"))
+			match a.checked_powi(&b) {
+				Some(ref res) if a.is_finite() && !res.is_finite() => Err(ARITHMETIC_OVERFLOW),
+				Some(res)	=> Ok(ATOM(NUMBER(res))),
+				None		=> Err(ARITHMETIC_OVERFLOW)
+			}
 		}
 	} else {
-		Err(INVALID_ARGUMENT_TYPE(args[0].clone(), "number"))
+		Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "number"))
 	}
 }
 
 pub fn powr(args: Vec<Datum>) -> Result<Datum, LispError> {
-	if args.len() != 2 {
-		Err(INVALID_NUMBER_OF_ARGS(args.len(), 2))
-	} else if let ATOM(NUMBER(a)) = args[0] {
-		if let ATOM(NUMBER(b)) = args[1] {
-			Ok(ATOM(NUMBER(REAL(
-				a.val().powf(b.val())).simplify())))
+	if let ATOM(NUMBER(ref a)) = args[0] {
+		if let ATOM(NUMBER(ref b)) = args[1] {
+			checked_op(a.clone(), b.clone(), |a,b| REAL(a.val().powf(b.val())))
+				.map(|res| ATOM(NUMBER(res.simplify())))
 		} else {
-			Err(INVALID_ARGUMENT_TYPE(args[1].clone(), "number"))
+			Err(INVALID_ARGUMENT_TYPE(Box::new(args[1].clone()), "number"))
 		}
 	} else {
-		Err(INVALID_ARGUMENT_TYPE(args[0].clone(), "number"))
+		Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "number"))
 	}
 }
 
 pub fn floor(args: Vec<Datum>) -> Result<Datum, LispError> {
-	if args.len() != 1 {
-		Err(INVALID_NUMBER_OF_ARGS(args.len(), 1))
-	} else if let ATOM(NUMBER(a)) = args[0] {
-		Ok(ATOM(NUMBER(REAL(a.val().floor()).simplify())))
+	if let ATOM(NUMBER(ref a)) = args[0] {
+		Ok(ATOM(NUMBER(a.floor().simplify())))
 	} else {
-		Err(INVALID_ARGUMENT_TYPE(args[0].clone(), "number"))
+		Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "number"))
 	}
 }
 
 pub fn ceil(args: Vec<Datum>) -> Result<Datum, LispError> {
-	if args.len() != 1 {
-		Err(INVALID_NUMBER_OF_ARGS(args.len(), 1))
-	} else if let ATOM(NUMBER(a)) = args[0] {
-		Ok(ATOM(NUMBER(REAL(a.val().ceil()).simplify())))
+	if let ATOM(NUMBER(ref a)) = args[0] {
+		Ok(ATOM(NUMBER(a.ceil().simplify())))
 	} else {
-		Err(INVALID_ARGUMENT_TYPE(args[0].clone(), "number"))
+		Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "number"))
 	}
 }
 
 pub fn type_lisp(args: Vec<Datum>) -> Result<Datum, LispError> {
-	if args.len() != 1 {
-		return Err(INVALID_NUMBER_OF_ARGS(args.len(), 1));
-	}
-
 	match args[0] {
-		ATOM(SYMBOL(_)) 			=> Ok(ATOM(SYMBOL("SYMBOL".to_string()))),
-		ATOM(STRING(_)) 			=> Ok(ATOM(SYMBOL("STRING".to_string()))),
-		ATOM(NUMBER(RATIONAL(..)))	=> Ok(ATOM(SYMBOL("RATIONAL".to_string()))),
-		ATOM(NUMBER(INTEGER(_)))	=> Ok(ATOM(SYMBOL("INTEGER".to_string()))),
-		ATOM(NUMBER(REAL(_)))		=> Ok(ATOM(SYMBOL("REAL".to_string()))),
-		ATOM(T)						=> Ok(ATOM(SYMBOL("BOOLEAN".to_string()))),
-		LIST(CONS(..))				=> Ok(ATOM(SYMBOL("CONS".to_string()))),
-		LIST(NIL)					=> Ok(ATOM(SYMBOL("NULL".to_string()))),
-		FUNCTION(SPECIAL(_))		=> Ok(ATOM(SYMBOL("SPECIAL FUNCTION".to_string()))),
-		FUNCTION(NATIVE(_))			=> Ok(ATOM(SYMBOL("NATIVE FUNCTION".to_string()))),
-		FUNCTION(LAMBDA(_))			=> Ok(ATOM(SYMBOL("LAMBDA EXPRESSION".to_string()))),
-		FUNCTION(MACRO(_))			=> Ok(ATOM(SYMBOL("MACRO".to_string())))
+		ATOM(SYMBOL(_)) 			=> Ok(ATOM(SYMBOL(Symbol::intern("SYMBOL")))),
+		ATOM(STRING(_)) 			=> Ok(ATOM(SYMBOL(Symbol::intern("STRING")))),
+		ATOM(NUMBER(RATIONAL(..)))	=> Ok(ATOM(SYMBOL(Symbol::intern("RATIONAL")))),
+		ATOM(NUMBER(INTEGER(_)))	=> Ok(ATOM(SYMBOL(Symbol::intern("INTEGER")))),
+		ATOM(NUMBER(REAL(_)))		=> Ok(ATOM(SYMBOL(Symbol::intern("REAL")))),
+		ATOM(NUMBER(BIGINT(_)))		=> Ok(ATOM(SYMBOL(Symbol::intern("INTEGER")))),
+		ATOM(NUMBER(BIGRATIONAL(_)))=> Ok(ATOM(SYMBOL(Symbol::intern("RATIONAL")))),
+		ATOM(CHAR(_))				=> Ok(ATOM(SYMBOL(Symbol::intern("CHARACTER")))),
+		ATOM(T)						=> Ok(ATOM(SYMBOL(Symbol::intern("BOOLEAN")))),
+		LIST(CONS(..))				=> Ok(ATOM(SYMBOL(Symbol::intern("CONS")))),
+		LIST(NIL)					=> Ok(ATOM(SYMBOL(Symbol::intern("NULL")))),
+		VECTOR(_)					=> Ok(ATOM(SYMBOL(Symbol::intern("VECTOR")))),
+		HASH_TABLE(_)				=> Ok(ATOM(SYMBOL(Symbol::intern("HASH-TABLE")))),
+		STRUCT(ref s)				=> Ok(ATOM(SYMBOL(s.borrow().name))),
+		FUNCTION(SPECIAL(_))		=> Ok(ATOM(SYMBOL(Symbol::intern("SPECIAL FUNCTION")))),
+		FUNCTION(NATIVE(_)) |
+		FUNCTION(FOREIGN(_))		=> Ok(ATOM(SYMBOL(Symbol::intern("NATIVE FUNCTION")))),
+		FUNCTION(LAMBDA(_))			=> Ok(ATOM(SYMBOL(Symbol::intern("LAMBDA EXPRESSION")))),
+		FUNCTION(MACRO(_))			=> Ok(ATOM(SYMBOL(Symbol::intern("MACRO"))))
 	}
 }
 
 pub fn is_atom(args: Vec<Datum>) -> Result<Datum, LispError> {
-	if args.len() != 1 {
-		Err(INVALID_NUMBER_OF_ARGS(args.len(), 1))
-	} else if let ATOM(_) = args[0] {
+	if let ATOM(_) = args[0] {
 		Ok(ATOM(T))
 	} else if let LIST(NIL) = args[0] {
 		Ok(ATOM(T))
@@ -348,9 +354,7 @@ pub fn is_atom(args: Vec<Datum>) -> Result<Datum, LispError> {
 }
 
 pub fn is_list(args: Vec<Datum>) -> Result<Datum, LispError> {
-	if args.len() != 1 {
-		Err(INVALID_NUMBER_OF_ARGS(args.len(), 1))
-	} else if let LIST(_) = args[0] {
+	if let LIST(_) = args[0] {
 		Ok(ATOM(T))
 	} else {
 		Ok(LIST(NIL))
@@ -358,9 +362,7 @@ pub fn is_list(args: Vec<Datum>) -> Result<Datum, LispError> {
 }
 
 pub fn is_cons(args: Vec<Datum>) -> Result<Datum, LispError> {
-	if args.len() != 1 {
-		Err(INVALID_NUMBER_OF_ARGS(args.len(), 1))
-	} else if let LIST(CONS(..)) = args[0] {
+	if let LIST(CONS(..)) = args[0] {
 		Ok(ATOM(T))
 	} else {
 		Ok(LIST(NIL))
@@ -368,9 +370,7 @@ pub fn is_cons(args: Vec<Datum>) -> Result<Datum, LispError> {
 }
 
 pub fn is_symbol(args: Vec<Datum>) -> Result<Datum, LispError> {
-	if args.len() != 1 {
-		Err(INVALID_NUMBER_OF_ARGS(args.len(), 1))
-	} else if let ATOM(SYMBOL(_)) = args[0] {
+	if let ATOM(SYMBOL(_)) = args[0] {
 		Ok(ATOM(T))
 	} else if let LIST(NIL) = args[0] {
 		Ok(ATOM(T))
@@ -379,33 +379,45 @@ pub fn is_symbol(args: Vec<Datum>) -> Result<Datum, LispError> {
 	}
 }
 
+//EQUAL? as a predicate, shared with CASE
+pub fn is_equal(a: &Datum, b: &Datum) -> bool {
+	equal_datum(a, b) || math_equal(vec![a.clone(), b.clone()]) == Ok(ATOM(T))
+}
+
 pub fn equal(args: Vec<Datum>) -> Result<Datum, LispError> {
-	if args.len() != 2 {
-		Err(INVALID_NUMBER_OF_ARGS(args.len(), 2))
-	} else if args[0] == args[1] {
-		Ok(ATOM(T))
-	} else if math_equal(args) == Ok(ATOM(T)) {
+	if is_equal(&args[0], &args[1]) {
 		Ok(ATOM(T))
 	} else {
 		Ok(LIST(NIL))
 	}
 }
 
-pub fn write_to_string(args: Vec<Datum>) -> Result<Datum, LispError> {
-	if args.len() != 1 {
-		Err(INVALID_NUMBER_OF_ARGS(args.len(), 1))
+//Conses, vectors, hash tables and structs are the same only if they are the
+//same object; atoms compare by value
+pub fn eq(args: Vec<Datum>) -> Result<Datum, LispError> {
+	let same = match (datum_id(&args[0]), datum_id(&args[1])) {
+		(None, None)	=> args[0] == args[1],
+		(a, b)			=> a == b
+	};
+	if same {
+		Ok(ATOM(T))
 	} else {
-		Ok(ATOM(STRING(format!("{}", args[0]))))
+		Ok(LIST(NIL))
 	}
 }
 
-pub fn read_from_string(args: Vec<Datum>) -> Result<Datum, LispError> {
-	if args.len() != 1 {
-		Err(INVALID_NUMBER_OF_ARGS(args.len(), 1))
-	} else if let ATOM(STRING(ref s)) = args[0] {
-		Ok(parse(&mut tokenize(s)))
+pub fn write_to_string(args: Vec<Datum>) -> Result<Datum, LispError> {
+	Ok(ATOM(STRING(format!("{}", args[0]))))
+}
+
+pub fn read_from_string(args: Vec<Datum>, preserve_case: bool) -> Result<Datum, LispError> {
+	if let ATOM(STRING(ref s)) = args[0] {
+		match tokenize(s, "<string>", preserve_case) {
+			Ok(mut tkns)	=> parse(&mut tkns),
+			Err(e)			=> Err(e)
+		}
 	} else {
-		Err(INVALID_ARGUMENT_TYPE(args[0].clone(), "string"))
+		Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "string"))
 	}
 }
 
@@ -415,16 +427,14 @@ pub fn string_concat(args: Vec<Datum>) -> Result<Datum, LispError> {
 		if let ATOM(STRING(s)) = arg {
 			ret.push_str(s.as_ref());
 		} else {
-			return Err(INVALID_ARGUMENT_TYPE(arg, "string"));
+			return Err(INVALID_ARGUMENT_TYPE(Box::new(arg), "string"));
 		}
 	}
 	Ok(ATOM(STRING(ret)))
 }
 
 pub fn not(args: Vec<Datum>) -> Result<Datum, LispError> {
-	if args.len() != 1 {
-		Err(INVALID_NUMBER_OF_ARGS(args.len(), 1))
-	} else if args[0] == LIST(NIL) {
+	if args[0] == LIST(NIL) {
 		Ok(ATOM(T))
 	} else {
 		Ok(LIST(NIL))
@@ -432,25 +442,19 @@ pub fn not(args: Vec<Datum>) -> Result<Datum, LispError> {
 }
 
 pub fn print(args: Vec<Datum>) -> Result<Datum, LispError> {
-	if args.len() != 1 {
-		Err(INVALID_NUMBER_OF_ARGS(args.len(), 1))
-	} else {
-		println!("{}", Red.paint(args[0].clone()));
-		Ok(args[0].clone())
-	}
+	println!("{}", Red.paint(args[0].clone()));
+	Ok(args[0].clone())
 }
 
 pub fn most(args: Vec<Datum>) -> Result<Datum, LispError> {
-	if args.len() != 1 {
-		Err(INVALID_NUMBER_OF_ARGS(args.len(), 1))
-	} else if let LIST(NIL) = args[0].clone() {
+	if let LIST(NIL) = args[0].clone() {
 		Ok(LIST(NIL))
 	} else if let LIST(lst) = args[0].clone() {
 		let mut v = lst.get_items();
 		v.pop();
 		Ok(LIST(List::from_vec(v)))
 	} else {
-		Err(INVALID_ARGUMENT_TYPE(args[0].clone(), "list"))
+		Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "list"))
 	}
 }
 
@@ -460,12 +464,12 @@ pub fn rand_int(args: Vec<Datum>) -> Result<Datum, LispError> {
 			if n > 0 {
 				Ok(ATOM(NUMBER(INTEGER(rand::thread_rng().gen_range(0,n)))))
 			} else {
-				Err(INVALID_ARGUMENT_TYPE(args[0].clone(), "positive integer"))
+				Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "positive integer"))
 			}
 		} else {
-			Err(INVALID_ARGUMENT_TYPE(args[0].clone(), "positive integer"))
+			Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "positive integer"))
 		}
-	} else if args.len() == 2 {
+	} else {
 		if let ATOM(NUMBER(INTEGER(m))) = args[0].clone() {
 			if let ATOM(NUMBER(INTEGER(n))) = args[1].clone() {
 				if n > m {
@@ -474,20 +478,16 @@ pub fn rand_int(args: Vec<Datum>) -> Result<Datum, LispError> {
 					Ok(ATOM(NUMBER(INTEGER(rand::thread_rng().gen_range(n,m)))))
 				}
 			} else {
-				Err(INVALID_ARGUMENT_TYPE(args[1].clone(), "integer"))
+				Err(INVALID_ARGUMENT_TYPE(Box::new(args[1].clone()), "integer"))
 			}
 		} else {
-			Err(INVALID_ARGUMENT_TYPE(args[0].clone(), "integer"))
+			Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "integer"))
 		}
-	} else {
-		Err(INVALID_NUMBER_OF_ARGS(args.len(), 1))
 	}
 }
 
-pub fn rand_bool(args: Vec<Datum>) -> Result<Datum, LispError> {
-	if args.len() != 0 {
-		Err(INVALID_NUMBER_OF_ARGS(args.len(), 0))
-	} else if rand::random() {
+pub fn rand_bool() -> Result<Datum, LispError> {
+	if rand::random() {
 		Ok(LIST(NIL))
 	} else {
 		Ok(ATOM(T))
@@ -495,7 +495,7 @@ pub fn rand_bool(args: Vec<Datum>) -> Result<Datum, LispError> {
 }
 
 pub fn rand_real(args: Vec<Datum>) -> Result<Datum, LispError> {
-	if args.len() == 0 {
+	if args.is_empty() {
 		Ok(ATOM(NUMBER(REAL(rand::thread_rng().gen_range(0f64,1f64)))))
 	} else if args.len() == 1 {
 		if let ATOM(NUMBER(n)) = args[0].clone() {
@@ -503,12 +503,12 @@ pub fn rand_real(args: Vec<Datum>) -> Result<Datum, LispError> {
 				Ok(ATOM(NUMBER(REAL(
 					rand::thread_rng().gen_range(0f64,n.val())).simplify())))
 			} else {
-				Err(INVALID_ARGUMENT_TYPE(args[0].clone(), "positive number"))
+				Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "positive number"))
 			}
 		} else {
-			Err(INVALID_ARGUMENT_TYPE(args[0].clone(), "positive number"))
+			Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "positive number"))
 		}
-	} else if args.len() == 2 {
+	} else {
 		if let ATOM(NUMBER(m)) = args[0].clone() {
 			if let ATOM(NUMBER(n)) = args[1].clone() {
 				if n > m {
@@ -519,13 +519,11 @@ pub fn rand_real(args: Vec<Datum>) -> Result<Datum, LispError> {
 						rand::thread_rng().gen_range(n.val(),m.val())).simplify())))
 				}
 			} else {
-				Err(INVALID_ARGUMENT_TYPE(args[1].clone(), "number"))
+				Err(INVALID_ARGUMENT_TYPE(Box::new(args[1].clone()), "number"))
 			}
 		} else {
-			Err(INVALID_ARGUMENT_TYPE(args[0].clone(), "number"))
+			Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "number"))
 		}
-	} else {
-		Err(INVALID_NUMBER_OF_ARGS(args.len(), 1))
 	}
 }
 
@@ -538,9 +536,7 @@ fn format_vec<T>(s: &str, v: Vec<T>) -> String where T: fmt::Binary + Clone {
 }
 
 pub fn format(args: Vec<Datum>) -> Result<Datum, LispError> {
-	if args.len() < 1 {
-		Err(INVALID_NUMBER_OF_ARGS(args.len(), 1))
-	} else if let ATOM(STRING(s)) = args[0].clone() {
+	if let ATOM(STRING(s)) = args[0].clone() {
 		let count = s.split("{}").count();
 		if count == args.len() {
 			let format_args = args[1..].to_vec();
@@ -549,6 +545,482 @@ pub fn format(args: Vec<Datum>) -> Result<Datum, LispError> {
 			Err(INVALID_NUMBER_OF_FORMAT_PARAMS(args.len()-1, count-1))
 		}
 	} else {
-		Err(INVALID_ARGUMENT_TYPE(args[0].clone(), "string"))
+		Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "string"))
+	}
+}
+
+pub fn is_char(args: Vec<Datum>) -> Result<Datum, LispError> {
+	if let ATOM(CHAR(_)) = args[0] {
+		Ok(ATOM(T))
+	} else {
+		Ok(LIST(NIL))
+	}
+}
+
+pub fn char_to_integer(args: Vec<Datum>) -> Result<Datum, LispError> {
+	if let ATOM(CHAR(c)) = args[0] {
+		Ok(ATOM(NUMBER(INTEGER(c as i64))))
+	} else {
+		Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "character"))
+	}
+}
+
+pub fn integer_to_char(args: Vec<Datum>) -> Result<Datum, LispError> {
+	let code = match args[0] {
+		ATOM(NUMBER(INTEGER(n))) if n >= 0 && n <= u32::MAX as i64 => Some(n as u32),
+		_ => None
+	};
+	match code.and_then(::std::char::from_u32) {
+		Some(c)	=> Ok(ATOM(CHAR(c))),
+		None	=> Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "unicode code point"))
+	}
+}
+
+//Characters whose case mapping is more than one character are left unchanged
+fn convert_case<I: Iterator<Item=char>>(c: char, mut mapped: I) -> char {
+	match (mapped.next(), mapped.next()) {
+		(Some(m), None)	=> m,
+		_				=> c
+	}
+}
+
+pub fn char_upcase(args: Vec<Datum>) -> Result<Datum, LispError> {
+	if let ATOM(CHAR(c)) = args[0] {
+		Ok(ATOM(CHAR(convert_case(c, c.to_uppercase()))))
+	} else {
+		Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "character"))
+	}
+}
+
+pub fn char_downcase(args: Vec<Datum>) -> Result<Datum, LispError> {
+	if let ATOM(CHAR(c)) = args[0] {
+		Ok(ATOM(CHAR(convert_case(c, c.to_lowercase()))))
+	} else {
+		Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "character"))
+	}
+}
+
+pub fn char_predicate(args: Vec<Datum>, pred: fn(char) -> bool) -> Result<Datum, LispError> {
+	if let ATOM(CHAR(c)) = args[0] {
+		Ok(if pred(c) {ATOM(T)} else {LIST(NIL)})
+	} else {
+		Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "character"))
+	}
+}
+
+fn string_arg(arg: &Datum) -> Result<String, LispError> {
+	match *arg {
+		ATOM(STRING(ref s))	=> Ok(s.clone()),
+		_					=> Err(INVALID_ARGUMENT_TYPE(Box::new(arg.clone()), "string"))
+	}
+}
+
+//Converts a character index into an index within a string of the given length
+fn index_arg(arg: &Datum, len: usize) -> Result<usize, LispError> {
+	match *arg {
+		ATOM(NUMBER(INTEGER(n))) if n >= 0 && n as usize <= len => Ok(n as usize),
+		ATOM(NUMBER(INTEGER(_)))	=> Err(INDEX_OUT_OF_BOUNDS(Box::new(arg.clone()), len)),
+		_							=> Err(INVALID_ARGUMENT_TYPE(Box::new(arg.clone()), "integer"))
+	}
+}
+
+pub fn string_length(args: Vec<Datum>) -> Result<Datum, LispError> {
+	string_arg(&args[0]).map(|s| ATOM(NUMBER(INTEGER(s.chars().count() as i64))))
+}
+
+pub fn substring(args: Vec<Datum>) -> Result<Datum, LispError> {
+	let chars: Vec<char> = match string_arg(&args[0]) {
+		Ok(s)	=> s.chars().collect(),
+		Err(e)	=> return Err(e)
+	};
+	let start = index_arg(&args[1], chars.len());
+	let end = if args.len() == 3 {index_arg(&args[2], chars.len())} else {Ok(chars.len())};
+	match (start, end) {
+		(Ok(start), Ok(end)) if start <= end
+							=> Ok(ATOM(STRING(chars[start..end].iter().collect()))),
+		(Ok(_), Ok(_))		=> Err(INDEX_OUT_OF_BOUNDS(Box::new(args[1].clone()), chars.len())),
+		(Err(e), _) | (_, Err(e)) => Err(e)
+	}
+}
+
+pub fn string_split(args: Vec<Datum>) -> Result<Datum, LispError> {
+	match (string_arg(&args[0]), string_arg(&args[1])) {
+		(Ok(_), Ok(ref sep)) if sep.is_empty()
+							=> Err(INVALID_ARGUMENT_TYPE(Box::new(args[1].clone()), "nonempty string")),
+		(Ok(s), Ok(sep))	=> Ok(LIST(List::from_vec(s.split(&sep[..])
+									.map(|part| ATOM(STRING(part.to_string()))).collect()))),
+		(Err(e), _) | (_, Err(e)) => Err(e)
+	}
+}
+
+pub fn string_join(args: Vec<Datum>) -> Result<Datum, LispError> {
+	let sep = if args.len() == 2 {string_arg(&args[1])} else {Ok(String::new())};
+	if let Err(e) = sep {
+		return Err(e);
+	}
+	if let LIST(ref lst) = args[0] {
+		let mut parts = vec![];
+		for item in lst.get_items() {
+			match string_arg(&item) {
+				Ok(s)	=> parts.push(s),
+				Err(e)	=> return Err(e)
+			}
+		}
+		Ok(ATOM(STRING(parts.join(&sep.ok().unwrap()))))
+	} else {
+		Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "list"))
 	}
-}
\ No newline at end of file
+}
+
+//Returns the character index of the first occurrence of the substring, or nil
+pub fn string_index(args: Vec<Datum>) -> Result<Datum, LispError> {
+	match (string_arg(&args[0]), string_arg(&args[1])) {
+		(Ok(s), Ok(sub))	=> match s.find(&sub[..]) {
+			Some(pos)	=> Ok(ATOM(NUMBER(INTEGER(s[..pos].chars().count() as i64)))),
+			None		=> Ok(LIST(NIL))
+		},
+		(Err(e), _) | (_, Err(e)) => Err(e)
+	}
+}
+
+pub fn string_replace(args: Vec<Datum>) -> Result<Datum, LispError> {
+	match (string_arg(&args[0]), string_arg(&args[1]), string_arg(&args[2])) {
+		(Ok(_), Ok(ref old), Ok(_)) if old.is_empty()
+							=> Err(INVALID_ARGUMENT_TYPE(Box::new(args[1].clone()), "nonempty string")),
+		(Ok(s), Ok(old), Ok(new)) => Ok(ATOM(STRING(s.replace(&old[..], &new)))),
+		(Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => Err(e)
+	}
+}
+
+pub fn string_upcase(args: Vec<Datum>) -> Result<Datum, LispError> {
+	string_arg(&args[0]).map(|s| ATOM(STRING(s.to_uppercase())))
+}
+
+pub fn string_downcase(args: Vec<Datum>) -> Result<Datum, LispError> {
+	string_arg(&args[0]).map(|s| ATOM(STRING(s.to_lowercase())))
+}
+
+pub fn string_trim(args: Vec<Datum>) -> Result<Datum, LispError> {
+	string_arg(&args[0]).map(|s| ATOM(STRING(s.trim().to_string())))
+}
+
+//Reads the string with the same rules as the parser. Returns nil when the
+//string is not a single number.
+pub fn string_to_number(args: Vec<Datum>) -> Result<Datum, LispError> {
+	let s = string_arg(&args[0]);
+	if let Err(e) = s {
+		return Err(e);
+	}
+	match tokenize(&s.ok().unwrap(), "<string>", false) {
+		Ok(ref tkns) if tkns.len() == 1 => match tkns[0].atom {
+			NUMBER(ref n)	=> Ok(ATOM(NUMBER(n.clone()))),
+			_				=> Ok(LIST(NIL))
+		},
+		_ => Ok(LIST(NIL))
+	}
+}
+
+pub fn number_to_string(args: Vec<Datum>) -> Result<Datum, LispError> {
+	if let ATOM(NUMBER(ref n)) = args[0] {
+		Ok(ATOM(STRING(format!("{}", n))))
+	} else {
+		Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "number"))
+	}
+}
+
+pub fn string_to_list(args: Vec<Datum>) -> Result<Datum, LispError> {
+	string_arg(&args[0]).map(|s| LIST(List::from_vec(s.chars().map(|c| ATOM(CHAR(c))).collect())))
+}
+
+pub fn list_to_string(args: Vec<Datum>) -> Result<Datum, LispError> {
+	if let LIST(ref lst) = args[0] {
+		let mut ret = String::new();
+		for item in lst.get_items() {
+			if let ATOM(CHAR(c)) = item {
+				ret.push(c);
+			} else {
+				return Err(INVALID_ARGUMENT_TYPE(Box::new(item), "character"));
+			}
+		}
+		Ok(ATOM(STRING(ret)))
+	} else {
+		Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "list"))
+	}
+}
+
+//Strings are compared lexicographically by code point
+pub fn compare_strings(args: Vec<Datum>, cmp: fn(&str, &str) -> bool) -> Result<Datum, LispError> {
+	for i in 1..args.len() {
+		match (string_arg(&args[i-1]), string_arg(&args[i])) {
+			(Ok(a), Ok(b))	=> if !cmp(&a, &b) {
+				return Ok(LIST(NIL));
+			},
+			(Err(e), _) | (_, Err(e)) => return Err(e)
+		}
+	}
+	Ok(ATOM(T))
+}
+
+pub fn vector_arg(arg: &Datum) -> Result<Rc<RefCell<Vec<Datum>>>, LispError> {
+	match *arg {
+		VECTOR(ref v)	=> Ok(v.clone()),
+		_				=> Err(INVALID_ARGUMENT_TYPE(Box::new(arg.clone()), "vector"))
+	}
+}
+
+fn vector_index(arg: &Datum, len: usize) -> Result<usize, LispError> {
+	match *arg {
+		ATOM(NUMBER(INTEGER(n))) if n >= 0 && (n as usize) < len => Ok(n as usize),
+		ATOM(NUMBER(INTEGER(_)))	=> Err(INDEX_OUT_OF_BOUNDS(Box::new(arg.clone()), len)),
+		_							=> Err(INVALID_ARGUMENT_TYPE(Box::new(arg.clone()), "integer"))
+	}
+}
+
+pub fn is_vector(args: Vec<Datum>) -> Result<Datum, LispError> {
+	if let VECTOR(_) = args[0] {
+		Ok(ATOM(T))
+	} else {
+		Ok(LIST(NIL))
+	}
+}
+
+//Longest vector make-vector will allocate, so a typo can't exhaust memory
+const MAX_VECTOR_LENGTH: i64 = 1 << 24;
+
+//(make-vector n [fill]), fill defaults to NIL
+pub fn make_vector(args: Vec<Datum>) -> Result<Datum, LispError> {
+	let fill = args.get(1).cloned().unwrap_or(LIST(NIL));
+	match args[0] {
+		ATOM(NUMBER(INTEGER(n))) if (0..=MAX_VECTOR_LENGTH).contains(&n) =>
+			Ok(Datum::vector(vec![fill; n as usize])),
+		ATOM(NUMBER(INTEGER(n))) if n >= 0	=> Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "length of at most 16777216")),
+		_									=> Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "non-negative integer"))
+	}
+}
+
+pub fn vector_ref(args: Vec<Datum>) -> Result<Datum, LispError> {
+	let v = vector_arg(&args[0])?;
+	let items = v.borrow();
+	vector_index(&args[1], items.len()).map(|i| items[i].clone())
+}
+
+pub fn vector_set(args: Vec<Datum>) -> Result<Datum, LispError> {
+	let v = vector_arg(&args[0])?;
+	let mut items = v.borrow_mut();
+	let len = items.len();
+	vector_index(&args[1], len).map(|i| {
+		items[i] = args[2].clone();
+		args[2].clone()
+	})
+}
+
+pub fn vector_length(args: Vec<Datum>) -> Result<Datum, LispError> {
+	vector_arg(&args[0]).map(|v| ATOM(NUMBER(INTEGER(v.borrow().len() as i64))))
+}
+
+pub fn vector_to_list(args: Vec<Datum>) -> Result<Datum, LispError> {
+	vector_arg(&args[0]).map(|v| LIST(List::from_vec(v.borrow().clone())))
+}
+
+pub fn list_to_vector(args: Vec<Datum>) -> Result<Datum, LispError> {
+	if let LIST(ref lst) = args[0] {
+		Ok(Datum::vector(lst.get_items()))
+	} else {
+		Err(INVALID_ARGUMENT_TYPE(Box::new(args[0].clone()), "list"))
+	}
+}
+
+pub fn hash_table_arg(arg: &Datum) -> Result<Rc<RefCell<HashTable>>, LispError> {
+	match *arg {
+		HASH_TABLE(ref h)	=> Ok(h.clone()),
+		_					=> Err(INVALID_ARGUMENT_TYPE(Box::new(arg.clone()), "hash table"))
+	}
+}
+
+pub fn make_hash_table() -> Result<Datum, LispError> {
+	Ok(Datum::hash_table(HashTable::new()))
+}
+
+pub fn is_hash_table(args: Vec<Datum>) -> Result<Datum, LispError> {
+	if let HASH_TABLE(_) = args[0] {
+		Ok(ATOM(T))
+	} else {
+		Ok(LIST(NIL))
+	}
+}
+
+//(hash-ref table key [default]), default defaults to NIL
+pub fn hash_ref(args: Vec<Datum>) -> Result<Datum, LispError> {
+	hash_table_arg(&args[0]).map(|h| match h.borrow().get(&args[1]) {
+		Some(value)	=> value.clone(),
+		None		=> args.get(2).cloned().unwrap_or(LIST(NIL))
+	})
+}
+
+pub fn hash_set(args: Vec<Datum>) -> Result<Datum, LispError> {
+	hash_table_arg(&args[0]).map(|h| {
+		h.borrow_mut().insert(args[1].clone(), args[2].clone());
+		args[2].clone()
+	})
+}
+
+//Returns T if the key was present
+pub fn hash_remove(args: Vec<Datum>) -> Result<Datum, LispError> {
+	hash_table_arg(&args[0]).map(|h| match h.borrow_mut().remove(&args[1]) {
+		Some(_)	=> ATOM(T),
+		None	=> LIST(NIL)
+	})
+}
+
+pub fn hash_keys(args: Vec<Datum>) -> Result<Datum, LispError> {
+	hash_table_arg(&args[0]).map(|h| LIST(List::from_vec(
+		h.borrow().entries().map(|(k, _)| k.clone()).collect())))
+}
+
+pub fn hash_values(args: Vec<Datum>) -> Result<Datum, LispError> {
+	hash_table_arg(&args[0]).map(|h| LIST(List::from_vec(
+		h.borrow().entries().map(|(_, v)| v.clone()).collect())))
+}
+
+pub fn hash_count(args: Vec<Datum>) -> Result<Datum, LispError> {
+	hash_table_arg(&args[0]).map(|h| ATOM(NUMBER(INTEGER(h.borrow().count() as i64))))
+}
+
+//Entries as a list of (key . value) pairs, e.g. for dolist
+pub fn hash_to_list(args: Vec<Datum>) -> Result<Datum, LispError> {
+	hash_table_arg(&args[0]).map(|h| LIST(List::from_vec(
+		h.borrow().entries().map(|(k, v)| LIST(List::cons(k.clone(), v.clone()))).collect())))
+}
+
+//Checks the optional struct name passed by the functions defstruct generates
+fn struct_arg(arg: &Datum, name: Option<&Datum>) -> Result<Rc<RefCell<Struct>>, LispError> {
+	match (arg, name) {
+		(STRUCT(s), None)		=> Ok(s.clone()),
+		(STRUCT(s), Some(&ATOM(SYMBOL(name)))) if s.borrow().name == name => Ok(s.clone()),
+		(_, Some(&ATOM(SYMBOL(name))))	=> Err(WRONG_STRUCT_TYPE(Box::new(arg.clone()), name)),
+		(_, Some(name))			=> Err(INVALID_ARGUMENT_TYPE(Box::new(name.clone()), "symbol")),
+		_						=> Err(INVALID_ARGUMENT_TYPE(Box::new(arg.clone()), "struct"))
+	}
+}
+
+//(make-struct 'name 'field value ...)
+pub fn make_struct(args: Vec<Datum>) -> Result<Datum, LispError> {
+	Datum::structure_from(&args)
+}
+
+//(struct? obj [name])
+pub fn is_struct(args: Vec<Datum>) -> Result<Datum, LispError> {
+	if struct_arg(&args[0], args.get(1)).is_ok() {
+		Ok(ATOM(T))
+	} else {
+		Ok(LIST(NIL))
+	}
+}
+
+//(struct-ref obj field [name])
+pub fn struct_ref(args: Vec<Datum>) -> Result<Datum, LispError> {
+	let (s, field) = match (struct_arg(&args[0], args.get(2)), field_name(&args[1])) {
+		(Ok(s), Ok(field))	=> (s, field),
+		(Err(e), _) | (_, Err(e)) => return Err(e)
+	};
+	let s = s.borrow();
+	match s.fields.iter().find(|&&(name, _)| name == field) {
+		Some((_, value))		=> Ok(value.clone()),
+		None					=> Err(UNKNOWN_FIELD(Box::new(args[0].clone()), field))
+	}
+}
+
+//(struct-set! obj field value [name])
+pub fn struct_set(args: Vec<Datum>) -> Result<Datum, LispError> {
+	let (s, field) = match (struct_arg(&args[0], args.get(3)), field_name(&args[1])) {
+		(Ok(s), Ok(field))	=> (s, field),
+		(Err(e), _) | (_, Err(e)) => return Err(e)
+	};
+	let mut s = s.borrow_mut();
+	match s.fields.iter_mut().find(|&&mut (name, _)| name == field) {
+		Some(&mut (_, ref mut value))	=> {
+			*value = args[2].clone();
+			Ok(args[2].clone())
+		},
+		None							=> Err(UNKNOWN_FIELD(Box::new(args[0].clone()), field))
+	}
+}
+
+//Builds the condition for error and signal, which take a condition object, a
+//format string and its arguments, or a condition type followed by either
+pub fn make_condition(args: Vec<Datum>, simple: &str) -> Result<Datum, LispError> {
+	let message = |args: Vec<Datum>| match format(args) {
+		Ok(m)	=> Ok(vec![(Symbol::intern("MESSAGE"), m)]),
+		Err(e)	=> Err(e)
+	};
+	match args.first() {
+		Some(&STRUCT(_)) if args.len() == 1 => Ok(args[0].clone()),
+		Some(&ATOM(STRING(_)))	=> message(args).map(|fields| Datum::structure(Symbol::intern(simple), fields)),
+		Some(&ATOM(SYMBOL(ty))) if args.len() == 1 => Ok(Datum::structure(ty, vec![])),
+		Some(&ATOM(SYMBOL(ty)))	=> message(args[1..].to_vec()).map(|fields| Datum::structure(ty, fields)),
+		Some(e)					=> Err(INVALID_ARGUMENT_TYPE(Box::new(e.clone()), "condition, string or symbol")),
+		None					=> Err(INVALID_NUMBER_OF_ARGS(0, 1))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use Interpreter;
+	use testing::*;
+
+	#[test]
+	fn characters_convert_and_classify() {
+		let mut lisp = Interpreter::new();
+		assert_eq!(run(&mut lisp, "(char->integer #\\a)"), int(97));
+		assert_eq!(run(&mut lisp, "(integer->char 955)"), ATOM(CHAR('\u{3bb}')));
+		assert_eq!(run(&mut lisp, "(char-upcase #\\a)"), ATOM(CHAR('A')));
+		assert_eq!(run(&mut lisp, "(char-downcase #\\A)"), ATOM(CHAR('a')));
+		assert_eq!(run(&mut lisp, "(list (char-alphabetic? #\\a) (char-numeric? #\\a) (char-whitespace? #\\space))"),
+				   run(&mut lisp, "'(t nil t)"));
+		assert_eq!(run(&mut lisp, "(char? #\\,)"), ATOM(T));
+		assert_eq!(format!("{}", run(&mut lisp, "(list #\\space #\\newline #\\,)")), "(#\\space #\\newline #\\,)");
+		assert!(lisp.eval_str("(integer->char 55296)").is_err());
+	}
+	#[test]
+	fn string_library() {
+		let mut lisp = Interpreter::new();
+		let string = |s: &str| ATOM(STRING(s.to_string()));
+		assert_eq!(run(&mut lisp, "(string-length \"h\u{e9}llo\")"), int(5));
+		assert_eq!(run(&mut lisp, "(substring \"hello\" 1 3)"), string("el"));
+		assert_eq!(run(&mut lisp, "(substring \"hello\" 2)"), string("llo"));
+		assert!(lisp.eval_str("(substring \"hello\" 4 9)").is_err());
+		assert_eq!(run(&mut lisp, "(string-split \"a,b,,c\" \",\")"), run(&mut lisp, "'(\"a\" \"b\" \"\" \"c\")"));
+		assert_eq!(run(&mut lisp, "(string-join '(\"a\" \"b\") \"-\")"), string("a-b"));
+		assert_eq!(run(&mut lisp, "(string-index \"hello\" \"ll\")"), int(2));
+		assert_eq!(run(&mut lisp, "(string-index \"hello\" \"z\")"), LIST(NIL));
+		assert_eq!(run(&mut lisp, "(string-replace \"a-b-c\" \"-\" \"+\")"), string("a+b+c"));
+		assert_eq!(run(&mut lisp, "(string-upcase \"MiXed\")"), string("MIXED"));
+		assert_eq!(run(&mut lisp, "(string-trim \"  x \")"), string("x"));
+		assert_eq!(run(&mut lisp, "(string->number \"3/4\")"), ATOM(NUMBER(RATIONAL(3, 4))));
+		assert_eq!(run(&mut lisp, "(string->number \"x\")"), LIST(NIL));
+		assert_eq!(run(&mut lisp, "(number->string 1/2)"), string("1/2"));
+		assert_eq!(run(&mut lisp, "(list->string (string->list \"abc\"))"), string("abc"));
+		assert_eq!(run(&mut lisp, "(list (string< \"apple\" \"banana\") (string= \"a\" \"a\" \"b\"))"),
+				   run(&mut lisp, "'(t nil)"));
+	}
+	#[test]
+	fn vectors_index_in_constant_time() {
+		let mut lisp = Interpreter::new();
+		run(&mut lisp, "(define v #(1 (2 3) \"four\"))");
+		assert_eq!(run(&mut lisp, "(vector-ref v 1)"), run(&mut lisp, "'(2 3)"));
+		assert_eq!(run(&mut lisp, "(progn (vector-set! v 0 'one) (vector-ref v 0))"), sym("ONE"));
+		assert_eq!(run(&mut lisp, "(vector-length (make-vector 3 0))"), int(3));
+		assert_eq!(run(&mut lisp, "(vector-ref (make-vector 2) 1)"), LIST(NIL));
+		assert_eq!(format!("{}", run(&mut lisp, "(vector 1 2)")), "#(1 2)");
+	}
+	#[test]
+	fn vectors_check_their_bounds() {
+		let v = Datum::vector(vec![int(1), int(2)]);
+		assert_eq!(vector_ref(vec![v.clone(), int(2)]), Err(INDEX_OUT_OF_BOUNDS(Box::new(int(2)), 2)));
+		assert_eq!(vector_ref(vec![v.clone(), int(-1)]), Err(INDEX_OUT_OF_BOUNDS(Box::new(int(-1)), 2)));
+		assert_eq!(vector_set(vec![v, int(5), int(0)]), Err(INDEX_OUT_OF_BOUNDS(Box::new(int(5)), 2)));
+		assert!(make_vector(vec![int(-1)]).is_err());
+		assert!(make_vector(vec![int(10000000000000)]).is_err());
+	}
+}
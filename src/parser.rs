@@ -5,108 +5,391 @@ use types::Datum::*;
 use types::Atom::*;
 use types::List::*;
 
-fn atomize(tkn: String) -> Atom {
-	if tkn.parse::<i64>().is_ok() {
+use errors::LispError;
+use errors::LispError::*;
+
+use num::{BigInt, BigRational, Zero};
+
+use std::collections::VecDeque;
+
+fn is_integer(tkn: &str) -> bool {
+	let digits = tkn.trim_start_matches(&['+', '-'][..]);
+	tkn.len() - digits.len() <= 1 && !digits.is_empty() &&
+	digits.chars().all(|c| c.is_ascii_digit())
+}
+
+//Inverse of types::char_name
+fn read_char(name: &str) -> Option<char> {
+	let mut chars = name.chars();
+	match (chars.next(), chars.next()) {
+		(Some(c), None)	=> return Some(c),
+		(None, _)		=> return None,
+		_				=> {}
+	}
+	match &name.to_lowercase()[..] {
+		"space"		=> Some(' '),
+		"newline"	=> Some('\n'),
+		"tab"		=> Some('\t'),
+		"return"	=> Some('\r'),
+		"nul"		=> Some('\0'),
+		s if s.starts_with("u{") && s.ends_with('}') =>
+			u32::from_str_radix(&s[2..s.len()-1], 16).ok().and_then(::std::char::from_u32),
+		_			=> None
+	}
+}
+
+fn atomize(tkn: String, preserve_case: bool) -> Atom {
+	if tkn.starts_with("#\\") && read_char(&tkn[2..]).is_some() {
+		CHAR(read_char(&tkn[2..]).unwrap())
+	} else if tkn.parse::<i64>().is_ok() {
 		NUMBER(INTEGER(tkn.parse::<i64>().unwrap()))
+	} else if is_integer(&tkn) {
+		NUMBER(BIGINT(tkn.parse::<BigInt>().unwrap()))
 	} else if tkn.parse::<f64>().is_ok() {
 		NUMBER(REAL(tkn.parse::<f64>().unwrap()))
-	} else if tkn.starts_with("\"") && tkn.ends_with("\"") {
-		unsafe {
-			STRING(tkn.slice_unchecked(1,tkn.len()-1).to_string())
-		}
-	} else if tkn.find('/') != None {
+	} else if tkn.find('/').is_some() {
 		let nums: Vec<String> = tkn.split('/').map(|s| s.to_string())
 								   .collect();
-		if nums[0].parse::<i64>().is_ok() && 
-		   nums[1].parse::<i64>().is_ok() && 
-		   nums.len() == 2 {
-		   	NUMBER(RATIONAL(nums[0].parse::<i64>().unwrap(),
-		   					nums[1].parse::<i64>().unwrap()).simplify())
-		} else {SYMBOL(tkn)}
+		//a zero denominator doesn't make a number, so 1/0 reads as a symbol
+		if nums.len() == 2 && 
+		   is_integer(&nums[0]) && 
+		   is_integer(&nums[1]) && !nums[1].parse::<BigInt>().unwrap().is_zero() {
+		   	NUMBER(BIGRATIONAL(BigRational::new(nums[0].parse::<BigInt>().unwrap(),
+		   										nums[1].parse::<BigInt>().unwrap())).simplify())
+		} else {SYMBOL(Symbol::intern(&tkn))}
 	} else if tkn.to_uppercase() == "T" {
 		T
+	} else if preserve_case {
+		SYMBOL(Symbol::intern(&tkn))
 	} else {
-		SYMBOL(tkn.to_uppercase())
+		SYMBOL(Symbol::intern(&tkn.to_uppercase()))
 	}
 }
 
-pub fn tokenize(s: &String) -> Vec<Atom> {
+//Decodes the characters following a backslash in a string literal. Returns
+//None while a \u{...} escape is still incomplete.
+fn unescape(seq: &str) -> Result<Option<char>, ()> {
+	match seq {
+		"n"		=> Ok(Some('\n')),
+		"t"		=> Ok(Some('\t')),
+		"r"		=> Ok(Some('\r')),
+		"0"		=> Ok(Some('\0')),
+		"\\"	=> Ok(Some('\\')),
+		"\""	=> Ok(Some('"')),
+		"u" | "u{" => Ok(None),
+		s if s.starts_with("u{") && s.ends_with('}') => {
+			u32::from_str_radix(&s[2..s.len()-1], 16).ok()
+				.and_then(char::from_u32).map(Some).ok_or(())
+		},
+		s if s.starts_with("u{") && s.len() <= 8 &&
+			 s[2..].chars().all(|c| c.is_ascii_hexdigit()) => Ok(None),
+		_		=> Err(())
+	}
+}
+
+//Symbols are upper-cased unless preserve_case is set
+pub fn tokenize(s: &str, file: &str, preserve_case: bool) -> Result<VecDeque<Token>, LispError> {
 	let mut curr = String::new();
-	let mut tkns: Vec<Atom> = Vec::new();
+	let mut tkns: VecDeque<Token> = VecDeque::new();
+	let mut prev = ' ';
+	let mut here = Span{file: file.to_string(), line: 1, column: 0};
+	let mut start = here.clone();
+	let mut in_string = false;
+	let mut escape: Option<(String, Span)> = None;
+	let mut line_comment = false;
+	let mut block_comments: Vec<Span> = vec![]; //start of each open #| comment
+	let mut comment_last = ' ';
 	for character in s.chars() {
+		if prev == '\n' {
+			here.line += 1;
+			here.column = 1;
+		} else {
+			here.column += 1;
+		}
+		let last = prev;
+		prev = character;
+
+		if in_string {
+			if let Some((mut seq, span)) = escape.take() {
+				seq.push(character);
+				match unescape(&seq) {
+					Ok(Some(c))	=> curr.push(c),
+					Ok(None)	=> escape = Some((seq, span)),
+					Err(_)		=> return Err(LOCATED(span, Box::new(INVALID_ESCAPE(seq))))
+				}
+			} else if character == '\\' {
+				escape = Some((String::new(), here.clone()));
+			} else if character == '"' {
+				tkns.push_back(Token{atom: STRING(curr.clone()), span: start.clone()});
+				curr = String::from("");
+				in_string = false;
+			} else {
+				curr.push(character);
+			}
+			continue;
+		} else if line_comment {
+			line_comment = character != '\n';
+			continue;
+		} else if !block_comments.is_empty() {
+			if comment_last == '|' && character == '#' {
+				block_comments.pop();
+				comment_last = ' ';
+			} else if comment_last == '#' && character == '|' {
+				block_comments.push(here.clone());
+				comment_last = ' ';
+			} else {
+				comment_last = character;
+			}
+			continue;
+		}
+
 		match character {
-			'"' if curr.starts_with('"') => {
-				curr.push('"');
-				tkns.push(atomize(curr.clone()));
+			//only names like #\space continue past the first character, so #\,@ is
+			//the character , followed by the symbol @
+			c if curr == "#\\" && !c.is_alphanumeric() => {
+				tkns.push_back(Token{atom: CHAR(c), span: start.clone()});
+				curr = String::from("");
+			}
+			c if curr == "#\\" => {
+				curr.push(c);
+			}
+			'(' if curr == "#" => {
+				tkns.push_back(Token{atom: SYMBOL(Symbol::intern("#(")), span: start.clone()});
+				curr = String::from("");
+			}
+			'(' if curr == "#H" || curr == "#h" => {
+				tkns.push_back(Token{atom: SYMBOL(Symbol::intern("#H(")), span: start.clone()});
+				curr = String::from("");
+			}
+			'(' if curr == "#S" || curr == "#s" => {
+				tkns.push_back(Token{atom: SYMBOL(Symbol::intern("#S(")), span: start.clone()});
+				curr = String::from("");
+			}
+			'|' if curr == "#" => {
+				curr = String::from("");
+				block_comments.push(start.clone());
+				comment_last = ' ';
+			}
+			';' if curr == "#" => {
+				tkns.push_back(Token{atom: SYMBOL(Symbol::intern("#;")), span: start.clone()});
 				curr = String::from("");
 			}
+			';' => {
+				if !curr.is_empty() {
+					tkns.push_back(Token{atom: atomize(curr.clone(), preserve_case), span: start.clone()})
+				}
+				curr = String::from("");
+				line_comment = true;
+			}
 			'"' => {
-				curr = String::from("\"");
+				if !curr.is_empty() {
+					tkns.push_back(Token{atom: atomize(curr.clone(), preserve_case), span: start.clone()})
+				}
+				curr = String::from("");
+				start = here.clone();
+				in_string = true;
 			}
-			c if curr.starts_with('"') => {
-				curr.push(c);
+			'@' if last == ',' && curr.is_empty() && is_comma(tkns.back()) => {
+				if let Some(comma) = tkns.pop_back() {
+					tkns.push_back(Token{atom: SYMBOL(Symbol::intern(",@")), span: comma.span});
+				}
 			}
-			c if vec!['(', ')', '\'', '`', ',', '[', ']'].contains(&c) => {
+			c if ['(', ')', '\'', '`', ',', '[', ']'].contains(&c) => {
 				if !curr.is_empty() {
-					tkns.push(atomize(curr.clone()))
+					tkns.push_back(Token{atom: atomize(curr.clone(), preserve_case), span: start.clone()})
 				}
-				tkns.push(atomize(c.to_string()));
+				tkns.push_back(Token{atom: atomize(c.to_string(), preserve_case), span: here.clone()});
 				curr = String::from("");
 			}
 			w if w.is_whitespace() => {
 				if !curr.is_empty() {
-					tkns.push(atomize(curr.clone()))
+					tkns.push_back(Token{atom: atomize(curr.clone(), preserve_case), span: start.clone()})
 				}
 				curr = String::from("")
 			}
 			c => {
+				if curr.is_empty() {
+					start = here.clone();
+				}
 				curr.push(c);
 			}
 		}
 	}
+	if in_string {
+		return Err(LOCATED(start, Box::new(UNTERMINATED_STRING)));
+	}
+	if let Some(span) = block_comments.pop() {
+		return Err(LOCATED(span, Box::new(UNTERMINATED_COMMENT)));
+	}
 	if !curr.is_empty() {
-		tkns.push(atomize(curr.clone()))
+		tkns.push_back(Token{atom: atomize(curr.clone(), preserve_case), span: start})
+	}
+	Ok(tkns)
+}
+
+fn is_comma(tkn: Option<&Token>) -> bool {
+	match tkn {
+		Some(&Token{atom: SYMBOL(ref s), ..})	=> *s == ",",
+		_										=> false
+	}
+}
+
+fn is_datum_comment(tkn: Option<&Token>) -> bool {
+	match tkn {
+		Some(&Token{atom: SYMBOL(ref s), ..})	=> *s == "#;",
+		_										=> false
+	}
+}
+
+//Drops every #; at the front of the token stream along with the form after it
+pub fn skip_datum_comments(tkns: &mut VecDeque<Token>) -> Result<(), LispError> {
+	while is_datum_comment(tkns.front()) {
+		let span = tkns.pop_front().unwrap().span;
+		if tkns.is_empty() {
+			return Err(LOCATED(span, Box::new(UNEXPECTED_EOF)));
+		}
+		parse(tkns)?;
+	}
+	Ok(())
+}
+
+//#H((key value) ...) as written by the printer
+fn hash_table_literal(entries: Vec<Datum>, span: Span) -> Result<Datum, LispError> {
+	let mut table = HashTable::new();
+	for entry in entries {
+		match entry {
+			LIST(ref pair) if pair.get_items().len() == 2 => {
+				let items = pair.get_items();
+				table.insert(items[0].clone(), items[1].clone());
+			},
+			e => return Err(LOCATED(span, Box::new(INVALID_ARGUMENT_TYPE(Box::new(e), "key-value pair"))))
+		}
 	}
-	tkns.clone()
+	Ok(Datum::hash_table(table))
 }
 
-pub fn parse(tkns: &mut Vec<Atom>) -> Datum {
-	match tkns.remove(0) {
+//Reads one form off the front of the token stream
+pub fn parse(tkns: &mut VecDeque<Token>) -> Result<Datum, LispError> {
+	if let Err(e) = skip_datum_comments(tkns) {
+		return Err(e);
+	} else if tkns.is_empty() {
+		return Err(NO_INPUT);
+	}
+
+	let Token{atom, span} = tkns.pop_front().unwrap();
+	match atom {
 		SYMBOL(s)	=> {
-			if s=="(" || s=="[" {
+			if s=="(" || s=="[" || s=="#(" || s=="#H(" || s=="#S(" {
 				let mut lst: Vec<Datum> = vec![];
-				while match tkns[0] 
-					{SYMBOL(ref s) => s!=")" && s!="]", _ => true} {
-						lst.push(parse(tkns))
+				loop {
+					skip_datum_comments(tkns)?;
+					let close = match tkns.front() {
+						None 	=> return Err(LOCATED(span, Box::new(UNCLOSED_PARENTHESIS))),
+						Some(&Token{atom: SYMBOL(c), ..}) if c==")" || c=="]" => c,
+						_		=> {
+							lst.push(parse(tkns)?);
+							continue;
+						}
+					};
+					let end = tkns.pop_front().unwrap(); //get rid of ")"
+					if (s!="[") != (close==")") {
+						return Err(LOCATED(end.span, Box::new(MISMATCHED_BRACKETS)));
+					} else if s=="#(" {
+						return Ok(Datum::vector(lst));
+					} else if s=="#H(" {
+						return hash_table_literal(lst, span);
+					} else if s=="#S(" {
+						return Datum::structure_from(&lst).map_err(|e| LOCATED(span, Box::new(e)));
 					}
-				tkns.remove(0); //get rid of "("
-				LIST(List::from_vec(lst))
-			} else if s=="'" {
-				LIST(CONS(
-					Box::new(ATOM(SYMBOL("QUOTE".to_string()))),
-					Box::new(LIST(CONS(
-						Box::new(parse(tkns)),
-						Box::new(LIST(NIL)))))))
-			} else if s=="`" {
-				LIST(CONS(
-					Box::new(ATOM(SYMBOL("BACKQUOTE".to_string()))),
-					Box::new(LIST(CONS(
-						Box::new(parse(tkns)),
-						Box::new(LIST(NIL)))))))
-			} else if s=="," {
-				LIST(CONS(
-					Box::new(ATOM(SYMBOL("COMMA".to_string()))),
-					Box::new(LIST(CONS(
-						Box::new(parse(tkns)),
-						Box::new(LIST(NIL)))))))
-			} else if s=="NIL" {
-				LIST(NIL)
+					let lst = List::from_vec(lst);
+					lst.set_span(span);
+					return Ok(LIST(lst));
+				}
+			} else if s==")" || s=="]" {
+				Err(LOCATED(span, Box::new(UNEXPECTED_CLOSE)))
+			} else if s=="'" || s=="`" || s=="," || s==",@" {
+				let name = match &*s.name() {
+					"'" => "QUOTE",
+					"`" => "BACKQUOTE",
+					"," => "COMMA",
+					_	=> "COMMA-AT"
+				};
+				if tkns.is_empty() {
+					return Err(LOCATED(span, Box::new(UNEXPECTED_EOF)));
+				}
+				let quoted = parse(tkns)?;
+				Ok(LIST(List::from_vec(vec![ATOM(SYMBOL(Symbol::intern(name))), quoted])))
+			} else if s.name().to_uppercase()=="NIL" {
+				Ok(LIST(NIL))
 			} else {
-				ATOM(SYMBOL(s))
+				Ok(ATOM(SYMBOL(s)))
 			}
 		},
-		e @ _ 		=> {
-			ATOM(e)
+		e			=> {
+			Ok(ATOM(e))
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn read(source: &str) -> Result<Datum, LispError> {
+		tokenize(source, "<test>", false).and_then(|mut tkns| parse(&mut tkns))
+	}
+
+	#[test]
+	fn parses_forms_one_at_a_time() {
+		let mut tkns = tokenize("(a #;(b) [c]) 'd", "<test>", false).unwrap();
+		assert_eq!(format!("{}", parse(&mut tkns).unwrap()), "(A (C))");
+		assert_eq!(format!("{}", parse(&mut tkns).unwrap()), "(QUOTE D)");
+		assert_eq!(parse(&mut tkns), Err(NO_INPUT));
+	}
+
+	#[test]
+	fn parses_long_lists() {
+		let source = format!("({})", "x ".repeat(200000));
+		match read(&source) {
+			Ok(LIST(lst))	=> assert_eq!(lst.get_items().len(), 200000),
+			_				=> panic!("expected a list")
 		}
 	}
-}
\ No newline at end of file
+	#[test]
+	fn zero_denominators_are_not_numbers() {
+		assert_eq!(read("1/0"), Ok(ATOM(SYMBOL(Symbol::intern("1/0")))));
+		assert_eq!(read("2/-00"), Ok(ATOM(SYMBOL(Symbol::intern("2/-00")))));
+		assert_eq!(read("2/4"), Ok(ATOM(NUMBER(RATIONAL(1, 2)))));
+	}
+	#[test]
+	fn reads_splices_only_after_a_comma_token() {
+		assert_eq!(format!("{}", read("`(a ,@b)").unwrap()), "(BACKQUOTE (A (COMMA-AT B)))");
+		assert_eq!(format!("{}", read("`(a , @b)").unwrap()), "(BACKQUOTE (A (COMMA @B)))");
+		assert_eq!(read("(\"x,\"@)").unwrap(), read("(\"x,\" @)").unwrap());
+	}
+	#[test]
+	fn skips_comments() {
+		assert_eq!(format!("{}", read("(a ; to the end of the line ) b\n b)").unwrap()), "(A B)");
+		assert_eq!(format!("{}", read("(a #| outer #| nested |# still outer |# b)").unwrap()), "(A B)");
+		assert_eq!(format!("{}", read("(a #;(b c) #; d e)").unwrap()), "(A E)");
+		assert_eq!(format!("{}", read("(\"; not a comment\" #\\;)").unwrap()), "(\"; not a comment\" #\\;)");
+		assert_eq!(read("(a #| never closed"), Err(LOCATED(Span{file: "<test>".to_string(), line: 1, column: 4},
+																Box::new(UNTERMINATED_COMMENT))));
+	}
+	#[test]
+	fn reads_characters() {
+		assert_eq!(read("#\\a"), Ok(ATOM(CHAR('a'))));
+		assert_eq!(read("#\\space"), Ok(ATOM(CHAR(' '))));
+		assert_eq!(read("#\\u{3bb}"), Ok(ATOM(CHAR('\u{3bb}'))));
+		assert_eq!(format!("{}", read("(#\\( #\\) #\\; #\\\")").unwrap()), "(#\\( #\\) #\\; #\\\")");
+		assert_eq!(format!("{}", read("(list #\\,@ b)").unwrap()), "(LIST #\\, @ B)");
+		assert_eq!(format!("{}", read("(#\\,@b)").unwrap()), "(#\\, @B)");
+		assert_eq!(format!("{}", read("`(#\\, ,@b)").unwrap()), "(BACKQUOTE (#\\, (COMMA-AT B)))");
+	}
+	#[test]
+	fn reads_string_escapes() {
+		assert_eq!(read(r#""a\"b\\c\n\t\u{41}""#), Ok(ATOM(STRING("a\"b\\c\n\tA".to_string()))));
+		assert_eq!(read("\"two\nlines\""), Ok(ATOM(STRING("two\nlines".to_string()))));
+		assert!(read(r#""\q""#).is_err());
+	}
+}
@@ -0,0 +1,22 @@
+//Helpers shared by the unit tests of every module
+use Interpreter;
+use types::*;
+use types::Datum::*;
+use types::Atom::*;
+use types::Number::*;
+
+pub fn int(n: i64) -> Datum {
+	ATOM(NUMBER(INTEGER(n)))
+}
+
+pub fn sym(name: &str) -> Datum {
+	ATOM(SYMBOL(Symbol::intern(name)))
+}
+
+//Evaluates the source, failing the test with the error message if it raises one
+pub fn run(lisp: &mut Interpreter, source: &str) -> Datum {
+	match lisp.eval_str(source) {
+		Ok(val)	=> val,
+		Err(e)	=> panic!("{} failed: {}", source, e.message())
+	}
+}
@@ -1,24 +1,89 @@
 use std::fmt;
+use std::mem;
 use std::ops;
 use std::cmp;
+use std::hash::{Hash, Hasher};
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
 
-#[derive(Clone, Debug, PartialEq)]
+use num::{BigInt, BigRational, Integer, One, Signed, ToPrimitive, Zero};
+
+use errors::LispError;
+use errors::LispError::*;
+use env::Env;
+
+#[derive(Clone, Debug)]
+#[allow(non_camel_case_types)]
 pub enum Datum {
 	ATOM(Atom),
 	LIST(List),
+	VECTOR(Rc<RefCell<Vec<Datum>>>),
+	HASH_TABLE(Rc<RefCell<HashTable>>),
+	STRUCT(Rc<RefCell<Struct>>),
 	FUNCTION(Function)
 }
 
 use self::Datum::*;
 
+//Compares like EQUAL?, so circular data can be compared without looping
+impl PartialEq for Datum {
+	fn eq(&self, other: &Datum) -> bool {
+		equal_datum(self, other)
+	}
+}
+
+impl Datum {
+	pub fn vector(items: Vec<Datum>) -> Datum {
+		VECTOR(Rc::new(RefCell::new(items)))
+	}
+
+	pub fn hash_table(table: HashTable) -> Datum {
+		HASH_TABLE(Rc::new(RefCell::new(table)))
+	}
+
+	pub fn structure(name: Symbol, fields: Vec<(Symbol, Datum)>) -> Datum {
+		STRUCT(Rc::new(RefCell::new(Struct{name, fields})))
+	}
+
+	//Builds a struct from its name followed by alternating field names and
+	//values, as written in #S(point :x 1 :y 2) and passed to make-struct
+	pub fn structure_from(items: &[Datum]) -> Result<Datum, LispError> {
+		if items.len() % 2 != 1 {
+			return Err(INVALID_ARG_LIST(Box::new(LIST(List::from_vec(items.to_vec())))));
+		}
+		let name = match items[0] {
+			ATOM(SYMBOL(name))	=> name,
+			_					=> return Err(INVALID_ARGUMENT_TYPE(Box::new(items[0].clone()), "symbol"))
+		};
+		let mut fields = Vec::with_capacity(items.len() / 2);
+		for pair in items[1..].chunks(2) {
+			match field_name(&pair[0]) {
+				Ok(field)	=> fields.push((field, pair[1].clone())),
+				Err(e)		=> return Err(e)
+			}
+		}
+		Ok(Datum::structure(name, fields))
+	}
+}
+
+//Field names may be written with or without a leading colon
+pub fn field_name(arg: &Datum) -> Result<Symbol, LispError> {
+	match *arg {
+		ATOM(SYMBOL(s)) if s.name().starts_with(':') => Ok(Symbol::intern(&s.name()[1..])),
+		ATOM(SYMBOL(s))	=> Ok(s),
+		_				=> Err(INVALID_ARGUMENT_TYPE(Box::new(arg.clone()), "symbol"))
+	}
+}
+
 impl fmt::Display for Datum {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		match *self {
 			FUNCTION(ref a)	=> write!(f, "{}", a),
 			ATOM(ref a)		=> write!(f, "{}", a),
-			LIST(ref a)		=> write!(f, "{}", a)
+			LIST(ref a)		=> write!(f, "{}", a),
+			VECTOR(_) | HASH_TABLE(_) | STRUCT(_) => write!(f, "{}", CirclePrinter::print(self))
 		}
 	}
 }
@@ -27,16 +92,22 @@ impl fmt::Binary for Datum {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		match *self {
 		    ATOM(STRING(ref s))	=> write!(f, "{}", s),
+		    ATOM(CHAR(c))		=> write!(f, "{}", c),
 		    _					=> write!(f, "{}", self)
 		}
 	}
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+//Largest exact result powi will compute, in bits (about 300,000 digits)
+const POWI_MAX_BITS: u64 = 1 << 20;
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum Number {
 	RATIONAL(i64,i64),
 	INTEGER(i64),
-	REAL(f64)
+	REAL(f64),
+	BIGINT(BigInt),
+	BIGRATIONAL(BigRational)
 }
 
 use self::Number::*;
@@ -46,124 +117,80 @@ impl fmt::Display for Number {
 		match *self {
 			RATIONAL(ref a, ref b)	=> write!(f, "{}/{}", a, b),
 			INTEGER(ref a)			=> write!(f, "{}", a),
-			REAL(ref a)				=> write!(f, "{}", a)
+			REAL(ref a)				=> write!(f, "{}", a),
+			BIGINT(ref a)			=> write!(f, "{}", a),
+			BIGRATIONAL(ref a)		=> write!(f, "{}/{}", a.numer(), a.denom())
 		}
 	}
 }
 
-impl ops::Add for Number {
-	type Output = Number;
-	fn add(self, rhs: Number) -> Number {
-		match self {
-			RATIONAL(a,b) => {
-				match rhs {
-					RATIONAL(c,d)	=> RATIONAL(a*d+b*c,b*d),
-					INTEGER(c) 		=> RATIONAL(a+c*b,b),
-					REAL(c)			=> REAL(a as f64/b as f64+c)
-				}
-			}
-			INTEGER(a) => {
-				match rhs {
-					RATIONAL(b,c)	=> RATIONAL(a*c+b,c),
-					INTEGER(b) 		=> INTEGER(a+b),
-					REAL(b)			=> REAL(a as f64+b)
-				}
-			},
-			REAL(a) => {
-				match rhs {
-					RATIONAL(b,c)	=> REAL(a+b as f64/c as f64),
-					INTEGER(b)		=> REAL(a+b as f64),
-					REAL(b)			=> REAL(a+b)
+type SmallOp = fn(i64, i64, i64, i64) -> Option<(i64, i64)>;
+
+//Applies an operation to two numbers, staying on the i64 path when possible
+//and promoting to arbitrary precision when the i64 computation overflows.
+//Exact results always come back in lowest terms.
+fn arith(lhs: Number, rhs: Number,
+		 small: SmallOp,
+		 real: fn(f64, f64) -> f64,
+		 big: fn(BigRational, BigRational) -> BigRational) -> Number {
+	match (lhs, rhs) {
+		(REAL(a), b)	=> REAL(real(a, b.val())),
+		(a, REAL(b))	=> REAL(real(a.val(), b)),
+		(a, b)			=> {
+			if let (Some((n1,d1)), Some((n2,d2))) = (a.small_ratio(), b.small_ratio()) {
+				if let Some((n, d)) = small(n1, d1, n2, d2) {
+					return RATIONAL(n, d).simplify();
 				}
 			}
+			BIGRATIONAL(big(a.big_ratio(), b.big_ratio())).simplify()
 		}
 	}
 }
 
+impl ops::Add for Number {
+	type Output = Number;
+	fn add(self, rhs: Number) -> Number {
+		arith(self, rhs,
+			  |a,b,c,d| Some((a.checked_mul(d)?.checked_add(b.checked_mul(c)?)?,
+			  				  b.checked_mul(d)?)),
+			  |a,b| a+b,
+			  |a,b| a+b)
+	}
+}
+
 impl ops::Sub for Number {
 	type Output = Number;
 	fn sub(self, rhs: Number) -> Number {
-		match self {
-			RATIONAL(a,b) => {
-				match rhs {
-					RATIONAL(c,d)	=> RATIONAL(a*d-b*c,b*d),
-					INTEGER(c) 		=> RATIONAL(a-c*b,b),
-					REAL(c)			=> REAL(a as f64/b as f64-c)
-				}
-			}
-			INTEGER(a) => {
-				match rhs {
-					RATIONAL(b,c)	=> RATIONAL(a*c-b,c),
-					INTEGER(b) 		=> INTEGER(a-b),
-					REAL(b)			=> REAL(a as f64-b)
-				}
-			},
-			REAL(a) => {
-				match rhs {
-					RATIONAL(b,c)	=> REAL(a-b as f64/c as f64),
-					INTEGER(b)		=> REAL(a-b as f64),
-					REAL(b)			=> REAL(a-b)
-				}
-			}
-		}
+		arith(self, rhs,
+			  |a,b,c,d| Some((a.checked_mul(d)?.checked_sub(b.checked_mul(c)?)?,
+			  				  b.checked_mul(d)?)),
+			  |a,b| a-b,
+			  |a,b| a-b)
 	}
 }
 
 impl ops::Mul for Number {
 	type Output = Number;
 	fn mul(self, rhs: Number) -> Number {
-		match self {
-			RATIONAL(a,b) => {
-				match rhs {
-					RATIONAL(c,d)	=> RATIONAL(a*c,b*d),
-					INTEGER(c) 		=> RATIONAL(a*c,b),
-					REAL(c)			=> REAL(a as f64/b as f64*c)
-				}
-			}
-			INTEGER(a) => {
-				match rhs {
-					RATIONAL(b,c)	=> RATIONAL(a*b,c),
-					INTEGER(b) 		=> INTEGER(a*b),
-					REAL(b)			=> REAL(a as f64*b)
-				}
-			},
-			REAL(a) => {
-				match rhs {
-					RATIONAL(b,c)	=> REAL(a*b as f64/c as f64),
-					INTEGER(b)		=> REAL(a*b as f64),
-					REAL(b)			=> REAL(a*b)
-				}
-			}
-		}
+		arith(self, rhs,
+			  |a,b,c,d| Some((a.checked_mul(c)?, b.checked_mul(d)?)),
+			  |a,b| a*b,
+			  |a,b| a*b)
 	}
 }
 
+//Dividing by an exact zero can't give a rational, so it gives the same
+//infinity or NaN as dividing by 0.0. The / builtin reports it as an error first.
 impl ops::Div for Number {
 	type Output = Number;
 	fn div(self, rhs: Number) -> Number {
-		match self {
-			RATIONAL(a,b) => {
-				match rhs {
-					RATIONAL(c,d)	=> RATIONAL(a*d,b*c),
-					INTEGER(c) 		=> RATIONAL(a,b*c),
-					REAL(c)			=> REAL(a as f64/b as f64/c)
-				}
-			}
-			INTEGER(a) => {
-				match rhs {
-					RATIONAL(b,c)	=> RATIONAL(a*c,b),
-					INTEGER(b) 		=> RATIONAL(a,b),
-					REAL(b)			=> REAL(a as f64/b)
-				}
-			},
-			REAL(a) => {
-				match rhs {
-					RATIONAL(b,c)	=> REAL(a/(b as f64/c as f64)),
-					INTEGER(b)		=> REAL(a/b as f64),
-					REAL(b)			=> REAL(a/b)
-				}
-			}
+		if rhs.is_exact() && rhs.is_zero() {
+			return REAL(self.val() / 0.);
 		}
+		arith(self, rhs,
+			  |a,b,c,d| Some((a.checked_mul(d)?, b.checked_mul(c)?)),
+			  |a,b| a/b,
+			  |a,b| a/b)
 	}
 }
 
@@ -171,36 +198,35 @@ impl ops::Neg for Number {
 	type Output = Number;
 	fn neg(self) -> Number {
 		match self {
-			RATIONAL(a,b) 	=> RATIONAL(-a,b),
-			INTEGER(a)		=> INTEGER(-a),
-			REAL(a)			=> REAL(-a)
+			RATIONAL(a,b) 	=> match a.checked_neg() {
+				Some(a)	=> RATIONAL(a,b),
+				None	=> BIGRATIONAL(-RATIONAL(a,b).big_ratio())
+			},
+			INTEGER(a)		=> match a.checked_neg() {
+				Some(a)	=> INTEGER(a),
+				None	=> BIGINT(-BigInt::from(a))
+			},
+			REAL(a)			=> REAL(-a),
+			BIGINT(a)		=> BIGINT(-a),
+			BIGRATIONAL(a)	=> BIGRATIONAL(-a)
 		}
 	}
 }
 
 impl cmp::PartialOrd for Number {
 	fn partial_cmp(&self, rhs: &Number) -> Option<cmp::Ordering> {
-		match *self {
-			RATIONAL(a,b) => {
-				match *rhs {
-					RATIONAL(c,d)	=> (a*d).partial_cmp(&(c*b)),
-					INTEGER(c) 		=> a.partial_cmp(&(c*b)),
-					REAL(c)			=> (a as f64/b as f64).partial_cmp(&c)
-				}
-			}
-			INTEGER(a) => {
-				match *rhs {
-					RATIONAL(b,c)	=> (a*c).partial_cmp(&b),
-					INTEGER(b) 		=> a.partial_cmp(&b),
-					REAL(b)			=> (a as f64).partial_cmp(&b)
-				}
-			},
-			REAL(a) => {
-				match *rhs {
-					RATIONAL(b,c)	=> a.partial_cmp(&(b as f64/c as f64)),
-					INTEGER(b)		=> a.partial_cmp(&(b as f64)),
-					REAL(b)			=> a.partial_cmp(&b)
+		match (self, rhs) {
+			(&INTEGER(a), &INTEGER(b))	=> a.partial_cmp(&b),
+			(&REAL(a), b)				=> a.partial_cmp(&b.val()),
+			(a, &REAL(b))				=> a.val().partial_cmp(&b),
+			(a, b)						=> {
+				//cross-multiplication can overflow, so fall back to arbitrary precision
+				if let (Some((n1,d1)), Some((n2,d2))) = (a.small_ratio(), b.small_ratio()) {
+					if let (Some(l), Some(r)) = (n1.checked_mul(d2), n2.checked_mul(d1)) {
+						return l.partial_cmp(&r);
+					}
 				}
+				a.big_ratio().partial_cmp(&b.big_ratio())
 			}
 		}
 	}
@@ -216,11 +242,11 @@ fn gcd(a: i64, b: i64) -> i64 {
 	}
 }
 
-use std::i64;
-
 impl Number {
 	pub fn simplify(&self) -> Number {
 		match *self {
+			RATIONAL(a,b) if a == i64::MIN || b == i64::MIN =>
+				BIGRATIONAL(self.big_ratio()).simplify(),
 			RATIONAL(a,b) if b < 0 => RATIONAL(-a,-b).simplify(),
 			RATIONAL(a,b) if a < 0 => -RATIONAL(-a,b).simplify(),
 			RATIONAL(a,b) 	=> {
@@ -231,119 +257,734 @@ impl Number {
 					RATIONAL(a/d, b/d)
 				}
 			},
-			REAL(a) if a == a.floor() && a <= i64::MAX as f64 && a >= i64::MIN as f64 => 
+			REAL(a) if a == a.floor() && a < i64::MAX as f64 && a >= i64::MIN as f64 =>
 				INTEGER(a as i64),
-			e @ _			=> e
+			BIGINT(ref a) if a.to_i64().is_some() => INTEGER(a.to_i64().unwrap()),
+			BIGRATIONAL(ref a) if a.is_integer() => BIGINT(a.to_integer()).simplify(),
+			BIGRATIONAL(ref a) if a.numer().to_i64().is_some() &&
+								  a.denom().to_i64().is_some() =>
+				RATIONAL(a.numer().to_i64().unwrap(), a.denom().to_i64().unwrap()),
+			ref e			=> e.clone()
 		}
 	}
 	pub fn val(&self) -> f64 {
 		match *self {
-			RATIONAL(a,b)	=> a as f64/b as f64,
-			INTEGER(a)		=> a as f64,
-			REAL(a)			=> a
+			RATIONAL(a,b)		=> a as f64/b as f64,
+			INTEGER(a)			=> a as f64,
+			REAL(a)				=> a,
+			BIGINT(ref a)		=> a.to_f64().unwrap_or(f64::NAN),
+			BIGRATIONAL(ref a)	=> a.to_f64().unwrap_or(f64::NAN)
+		}
+	}
+	pub fn floor(&self) -> Number {
+		match *self {
+			RATIONAL(a,b)		=> INTEGER(Integer::div_floor(&a, &b)),
+			REAL(a)				=> REAL(a.floor()),
+			BIGRATIONAL(ref a)	=> BIGINT(a.floor().to_integer()).simplify(),
+			ref e				=> e.clone()
+		}
+	}
+	pub fn ceil(&self) -> Number {
+		match *self {
+			RATIONAL(a,b)		=> INTEGER(Integer::div_ceil(&a, &b)),
+			REAL(a)				=> REAL(a.ceil()),
+			BIGRATIONAL(ref a)	=> BIGINT(a.ceil().to_integer()).simplify(),
+			ref e				=> e.clone()
+		}
+	}
+
+	pub fn is_finite(&self) -> bool {
+		match *self {
+			REAL(a)	=> a.is_finite(),
+			_		=> true
+		}
+	}
+	//Numeric equality as used by =. Exact numbers are compared exactly, and
+	//only a comparison involving a real goes through floats.
+	pub fn same_value(&self, rhs: &Number) -> bool {
+		self.partial_cmp(rhs) == Some(cmp::Ordering::Equal)
+	}
+	//Exact numbers too small for a float still aren't zero
+	pub fn is_zero(&self) -> bool {
+		match *self {
+			RATIONAL(a, _) | INTEGER(a)	=> a == 0,
+			REAL(a)						=> a == 0.,
+			BIGINT(ref a)				=> a.is_zero(),
+			BIGRATIONAL(ref a)			=> a.is_zero()
+		}
+	}
+	pub fn is_exact(&self) -> bool {
+		!matches!(*self, REAL(_))
+	}
+	//0, 1 and -1 stay small whatever the exponent. Any other exact base is
+	//refused once the result would need more than POWI_MAX_BITS, estimated as
+	//the exponent times the bit length of the base.
+	pub fn checked_powi(&self, exp: &BigInt) -> Option<Number> {
+		if !self.is_exact() {
+			return match exp.to_i32() {
+				Some(exp)	=> Some(REAL(self.val().powi(exp)).simplify()),
+				None		=> exp.to_f64().map(|exp| REAL(self.val().powf(exp)).simplify())
+			};
+		}
+		let base = self.big_ratio();
+		if exp.is_zero() || base.is_one() {
+			Some(INTEGER(1))
+		} else if base.is_zero() {
+			Some(INTEGER(0))
+		} else if base == -BigRational::one() {
+			Some(INTEGER(if exp.is_even() {1} else {-1}))
+		} else {
+			let bits = cmp::max(base.numer().bits(), base.denom().bits());
+			match (exp.abs() * bits).to_u64() {
+				Some(n) if n <= POWI_MAX_BITS	=> exp.to_i32().map(|exp| BIGRATIONAL(base.pow(exp)).simplify()),
+				_								=> None
+			}
+		}
+	}
+
+	fn small_ratio(&self) -> Option<(i64, i64)> {
+		match *self {
+			RATIONAL(a,b)	=> Some((a,b)),
+			INTEGER(a)		=> Some((a,1)),
+			_				=> None
+		}
+	}
+	fn big_ratio(&self) -> BigRational {
+		match *self {
+			RATIONAL(a,b)		=> BigRational::new(BigInt::from(a), BigInt::from(b)),
+			INTEGER(a)			=> BigRational::from_integer(BigInt::from(a)),
+			REAL(a)				=> BigRational::from_float(a).unwrap_or(BigRational::zero()),
+			BIGINT(ref a)		=> BigRational::from_integer(a.clone()),
+			BIGRATIONAL(ref a)	=> a.clone()
 		}
 	}
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Atom {
-	SYMBOL(String),
+	SYMBOL(Symbol),
 	STRING(String),
 	NUMBER(Number),
+	CHAR(char),
 	T
 }
 
 use self::Atom::*;
 
+//Name used by the reader and printer for a character, e.g. space in #\space
+pub fn char_name(c: char) -> String {
+	match c {
+		' '					=> "space".to_string(),
+		'\n'				=> "newline".to_string(),
+		'\t'				=> "tab".to_string(),
+		'\r'				=> "return".to_string(),
+		'\0'				=> "nul".to_string(),
+		c if c.is_control()	=> format!("u{{{:x}}}", c as u32),
+		c					=> c.to_string()
+	}
+}
+
+//Inverse of the escapes understood by the tokenizer, so printed strings can be
+//read back in
+fn escape(s: &str) -> String {
+	let mut escaped = String::with_capacity(s.len());
+	for c in s.chars() {
+		match c {
+			'"'					=> escaped.push_str("\\\""),
+			'\\'				=> escaped.push_str("\\\\"),
+			'\n'				=> escaped.push_str("\\n"),
+			'\t'				=> escaped.push_str("\\t"),
+			'\r'				=> escaped.push_str("\\r"),
+			'\0'				=> escaped.push_str("\\0"),
+			c if c.is_control()	=> escaped.push_str(&format!("\\u{{{:x}}}", c as u32)),
+			c					=> escaped.push(c)
+		}
+	}
+	escaped
+}
+
 impl fmt::Display for Atom {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		match *self {
 			SYMBOL(ref a)	=> write!(f, "{}", a),
-			STRING(ref a)	=> write!(f, "\"{}\"", a),
+			STRING(ref a)	=> write!(f, "\"{}\"", escape(a)),
 			NUMBER(ref a)	=> write!(f, "{}", a),
+			CHAR(c)			=> write!(f, "#\\{}", char_name(c)),
 			T 				=> write!(f, "T")
 		}
 	}
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug)]
 pub enum List {
-	CONS(Box<Datum>,Box<Datum>),
+	CONS(Rc<RefCell<Datum>>,Rc<RefCell<Datum>>),
 	NIL
 }
 
 use self::List::*;
 
+impl PartialEq for List {
+	fn eq(&self, other: &List) -> bool {
+		equal_datum(&LIST(self.clone()), &LIST(other.clone()))
+	}
+}
+
 impl fmt::Display for List {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		match *self {
 			NIL 		=> write!(f, "NIL"),
-			CONS(..)	=> write!(f, "{}", self.list_print())
+			CONS(..)	=> write!(f, "{}", CirclePrinter::print(&LIST(self.clone())))
+		}
+	}
+}
+
+//Dropping the last reference to a long list would otherwise recurse once per
+//cell. Unshared cdrs are detached and dropped in a loop instead.
+impl Drop for List {
+	fn drop(&mut self) {
+		let mut next = match *self {
+			CONS(_, ref cdr)	=> take_unshared(cdr),
+			NIL					=> None
+		};
+		while let Some(LIST(l)) = next {
+			next = match l {
+				CONS(_, ref cdr)	=> take_unshared(cdr),
+				NIL					=> None
+			};
 		}
 	}
 }
 
+fn take_unshared(cell: &Rc<RefCell<Datum>>) -> Option<Datum> {
+	if Rc::strong_count(cell) == 1 {
+		Some(mem::replace(&mut *cell.borrow_mut(), LIST(NIL)))
+	} else {
+		None
+	}
+}
+
+//Cells are shared through Rc, so cloning a list or taking its car or cdr never
+//copies the rest of the list. Each half of a cell sits in a RefCell so that
+//set-car! and set-cdr! can change it in place.
 impl List {
+	pub fn cons(car: Datum, cdr: Datum) -> List {
+		CONS(Rc::new(RefCell::new(car)), Rc::new(RefCell::new(cdr)))
+	}
+
 	pub fn from_vec(items: Vec<Datum>) -> List {
-		if items.is_empty() {
-			NIL
-		} else {
-			CONS(Box::new(items[0].clone()), Box::new(LIST(List::from_vec(
-												items[1..].to_vec()))))
+		let mut lst = NIL;
+		for item in items.into_iter().rev() {
+			lst = List::cons(item, LIST(lst));
 		}
+		lst
 	}
 
+	//A circular list gives each of its cells once. A second pointer moving at
+	//half speed catches up with the first only if the list loops back.
 	pub fn get_items(&self) -> Vec<Datum> {
-		match *self {
-			CONS(ref l, ref r)	=> {
-				let mut ret = vec![*l.clone()];
-				match **r {
-					LIST(ref a) => {ret.extend(a.get_items())},
-					_			=> {ret.push(*r.clone())}
-				}
-				ret
-			},
-			NIL 				=> vec![]
+		let mut ret = vec![];
+		let mut curr = self.clone();
+		let mut slow = self.clone();
+		while let CONS(ref l, ref r) = curr.clone() {
+			ret.push(l.borrow().clone());
+			let next = r.borrow().clone();
+			match next {
+				LIST(a)	=> curr = a,
+				e		=> {ret.push(e); break}
+			}
+			if ret.len() % 2 == 0 {
+				slow = slow.next_cell();
+			}
+			if curr.cell_id().is_some() && curr.cell_id() == slow.cell_id() {
+				ret.truncate(self.distinct_cells(&curr));
+				break;
+			}
 		}
+		ret
 	}
-	
-	fn last(&self) -> Datum {
-		match *self {
-			CONS(_, ref r)	=> match **r {
-				LIST(ref l)	=> l.last(),
-				ref e @ _	=> e.clone()
-			},
-			NIL						=> LIST(NIL)
+
+	fn next_cell(&self) -> List {
+		match self.cdr() {
+			LIST(l)	=> l,
+			_		=> NIL
 		}
 	}
 
-	fn list_print(&self) -> String {
-		let mut ret = "(".to_string();
-		let items = self.get_items();
-		for i in 0..items.len()-1 {
-			ret = format!("{}{} ", ret, items[i]);
+	//How many cells a circular list has, given a cell inside its loop
+	fn distinct_cells(&self, looped: &List) -> usize {
+		let mut period = 1;
+		let mut curr = looped.next_cell();
+		while curr.cell_id() != looped.cell_id() {
+			curr = curr.next_cell();
+			period += 1;
 		}
-		if self.last() == LIST(NIL) {
-			ret = format!("{}{})", ret, *items.last().unwrap());
-		} else {
-			ret = format!("{}. {})", ret, *items.last().unwrap());
+		let mut start = 0;
+		let (mut a, mut b) = (self.clone(), self.clone());
+		for _ in 0..period {
+			b = b.next_cell();
 		}
-		ret
+		while a.cell_id() != b.cell_id() {
+			a = a.next_cell();
+			b = b.next_cell();
+			start += 1;
+		}
+		start + period
 	}
 
 	pub fn car(&self) -> Datum {
 		match *self {
-			CONS(ref c, _)	=> *c.clone(),
+			CONS(ref c, _)	=> c.borrow().clone(),
 			NIL 			=> LIST(NIL)
 		}
 	}
 
 	pub fn cdr(&self) -> Datum {
 		match *self {
-			CONS(_, ref c)  => *c.clone(),
+			CONS(_, ref c)  => c.borrow().clone(),
 			NIL  			=> LIST(NIL)
 		}
 	}
+
+	//Follows n cdrs, sharing the rest of the list instead of copying it
+	pub fn nth_cdr(&self, n: usize) -> Datum {
+		let mut curr = LIST(self.clone());
+		for _ in 0..n {
+			curr = match curr {
+				LIST(ref l@CONS(..))	=> l.cdr(),
+				_						=> return LIST(NIL)
+			};
+		}
+		curr
+	}
+
+	//Identity of the cell, used by eq? and to find cycles when printing
+	pub fn cell_id(&self) -> Option<usize> {
+		match *self {
+			CONS(ref c, _)	=> Some(Rc::as_ptr(c) as usize),
+			NIL				=> None
+		}
+	}
+}
+
+//Identity of a mutable object, used by eq? and to find cycles when printing
+pub fn datum_id(d: &Datum) -> Option<usize> {
+	match *d {
+		LIST(ref l)			=> l.cell_id(),
+		VECTOR(ref v)		=> Some(Rc::as_ptr(v) as usize),
+		HASH_TABLE(ref h)	=> Some(Rc::as_ptr(h) as usize),
+		STRUCT(ref s)		=> Some(Rc::as_ptr(s) as usize),
+		_					=> None
+	}
+}
+
+//Depth-first walk recording every cell or vector that can be reached from
+//itself. Cdrs are followed in a loop so long lists don't overflow the stack.
+fn find_cycles(d: &Datum, path: &mut HashSet<usize>, seen: &mut HashSet<usize>,
+			   cycles: &mut HashMap<usize, Option<usize>>) {
+	let mut entered = vec![];
+	let mut curr = d.clone();
+	while let Some(id) = datum_id(&curr) {
+		if path.contains(&id) {
+			cycles.insert(id, None);
+			break;
+		} else if !seen.insert(id) {
+			break;
+		}
+		path.insert(id);
+		entered.push(id);
+		curr = match curr {
+			LIST(ref l)		=> {
+				find_cycles(&l.car(), path, seen, cycles);
+				l.cdr()
+			},
+			VECTOR(ref v)	=> {
+				for item in v.borrow().iter() {
+					find_cycles(item, path, seen, cycles);
+				}
+				break;
+			},
+			HASH_TABLE(ref h) => {
+				for (key, value) in h.borrow().entries() {
+					find_cycles(key, path, seen, cycles);
+					find_cycles(value, path, seen, cycles);
+				}
+				break;
+			},
+			STRUCT(ref s)	=> {
+				for (_, value) in &s.borrow().fields {
+					find_cycles(value, path, seen, cycles);
+				}
+				break;
+			},
+			_				=> break
+		};
+	}
+	for id in entered {
+		path.remove(&id);
+	}
+}
+
+//Prints lists and vectors, labelling the ones that are part of a cycle with
+//#n= the first time they are written and #n# every time after that
+struct CirclePrinter {
+	labels: HashMap<usize, Option<usize>>,
+	next: usize
+}
+
+impl CirclePrinter {
+	fn print(d: &Datum) -> String {
+		let mut labels = HashMap::new();
+		find_cycles(d, &mut HashSet::new(), &mut HashSet::new(), &mut labels);
+		let mut printer = CirclePrinter{labels, next: 0};
+		let mut out = String::new();
+		printer.write_datum(d, &mut out);
+		out
+	}
+
+	//Returns true if a back reference was written in place of the datum
+	fn write_label(&mut self, d: &Datum, out: &mut String) -> bool {
+		let next = self.next;
+		let label = match datum_id(d).and_then(|id| self.labels.get_mut(&id)) {
+			Some(label)	=> label,
+			None		=> return false
+		};
+		if let Some(n) = *label {
+			out.push_str(&format!("#{}#", n));
+			return true;
+		}
+		*label = Some(next);
+		out.push_str(&format!("#{}=", next));
+		self.next += 1;
+		false
+	}
+
+	fn write_datum(&mut self, d: &Datum, out: &mut String) {
+		match *d {
+			LIST(ref l@CONS(..))	=> self.write_list(l, out),
+			VECTOR(ref v)			=> {
+				if self.write_label(d, out) {
+					return;
+				}
+				out.push_str("#(");
+				for (i, item) in v.borrow().iter().enumerate() {
+					if i > 0 {
+						out.push(' ');
+					}
+					self.write_datum(item, out);
+				}
+				out.push(')');
+			},
+			HASH_TABLE(ref h)		=> {
+				if self.write_label(d, out) {
+					return;
+				}
+				out.push_str("#H(");
+				for (i, (key, value)) in h.borrow().entries().enumerate() {
+					if i > 0 {
+						out.push(' ');
+					}
+					out.push('(');
+					self.write_datum(key, out);
+					out.push(' ');
+					self.write_datum(value, out);
+					out.push(')');
+				}
+				out.push(')');
+			},
+			STRUCT(ref s)			=> {
+				if self.write_label(d, out) {
+					return;
+				}
+				let s = s.borrow();
+				out.push_str(&format!("#S({}", s.name));
+				for &(name, ref value) in &s.fields {
+					out.push_str(&format!(" :{} ", name));
+					self.write_datum(value, out);
+				}
+				out.push(')');
+			},
+			ref e					=> out.push_str(&e.to_string())
+		}
+	}
+
+	fn write_list(&mut self, lst: &List, out: &mut String) {
+		if self.write_label(&LIST(lst.clone()), out) {
+			return;
+		}
+		out.push('(');
+		let mut curr = lst.clone();
+		loop {
+			self.write_datum(&curr.car(), out);
+			match curr.cdr() {
+				LIST(NIL)	=> break,
+				LIST(next)	=> {
+					if next.cell_id().is_some_and(|id| self.labels.contains_key(&id)) {
+						out.push_str(" . ");
+						self.write_list(&next, out);
+						break;
+					}
+					out.push(' ');
+					curr = next;
+				},
+				e			=> {
+					out.push_str(" . ");
+					self.write_datum(&e, out);
+					break;
+				}
+			}
+		}
+		out.push(')');
+	}
+}
+
+//Wraps a Datum so it can key a HashMap with the same meaning as EQUAL?:
+//numbers compare by value, everything else structurally. Unlike EQUAL?, NaN
+//matches itself so a NaN key can be looked up again.
+#[derive(Clone, Debug)]
+struct HashKey(Datum);
+
+impl PartialEq for HashKey {
+	fn eq(&self, other: &HashKey) -> bool {
+		match (&self.0, &other.0) {
+			(&ATOM(NUMBER(ref a)), &ATOM(NUMBER(ref b)))	=> a.same_value(b) ||
+															   (a.val().is_nan() && b.val().is_nan()),
+			(a, b)											=> equal_datum(a, b)
+		}
+	}
+}
+
+impl Eq for HashKey {}
+
+//The float equal to the number, if there is one
+fn exact_float(n: &Number) -> Option<f64> {
+	match *n {
+		REAL(a)										=> Some(a),
+		INTEGER(a) if a.unsigned_abs() <= 1 << 53	=> Some(a as f64),
+		_											=> {
+			let val = n.val();
+			if val.is_finite() && BigRational::from_float(val) == Some(n.big_ratio()) {
+				Some(val)
+			} else {
+				None
+			}
+		}
+	}
+}
+
+//Positions of the conses, vectors, hash tables and structs currently being
+//walked, so a walk that comes back to one of them knows it has found a cycle
+type Path = HashMap<usize, usize>;
+
+fn leave(path: &mut Path, entered: Vec<usize>) {
+	for id in entered {
+		path.remove(&id);
+	}
+}
+
+//Structural equality as used by EQUAL?. Two circular structures are equal when
+//they have the same contents and their cycles lead back to the same position,
+//so comparing them terminates.
+pub fn equal_datum(a: &Datum, b: &Datum) -> bool {
+	equal_walk(a, b, &mut HashMap::new(), &mut HashMap::new())
+}
+
+fn equal_walk(a: &Datum, b: &Datum, path_a: &mut Path, path_b: &mut Path) -> bool {
+	let (mut entered_a, mut entered_b) = (vec![], vec![]);
+	let (mut a, mut b) = (a.clone(), b.clone());
+	let res = loop {
+		let (id_a, id_b) = match (datum_id(&a), datum_id(&b)) {
+			(Some(id_a), Some(id_b))	=> (id_a, id_b),
+			_							=> break match (&a, &b) {
+				(ATOM(x), ATOM(y))				=> x == y,
+				(LIST(NIL), LIST(NIL))			=> true,
+				(FUNCTION(x), FUNCTION(y))		=> x == y,
+				_								=> false
+			}
+		};
+		match (path_a.get(&id_a), path_b.get(&id_b)) {
+			(Some(x), Some(y))	=> break x == y,
+			(None, None)		=> {},
+			_					=> break false
+		}
+		let depth = path_a.len();
+		path_a.insert(id_a, depth);
+		path_b.insert(id_b, depth);
+		entered_a.push(id_a);
+		entered_b.push(id_b);
+		let next = match (&a, &b) {
+			(LIST(x), LIST(y))							=> {
+				if !equal_walk(&x.car(), &y.car(), path_a, path_b) {
+					break false;
+				}
+				(x.cdr(), y.cdr())
+			},
+			(VECTOR(x), VECTOR(y))						=> {
+				let (x, y) = (x.borrow(), y.borrow());
+				break x.len() == y.len() &&
+					  x.iter().zip(y.iter()).all(|(x, y)| equal_walk(x, y, path_a, path_b))
+			},
+			(HASH_TABLE(x), HASH_TABLE(y))				=> {
+				let (x, y) = (x.borrow(), y.borrow());
+				break x.count() == y.count() &&
+					  x.entries().all(|(key, x)| match y.get(key) {
+						  Some(y)	=> equal_walk(x, y, path_a, path_b),
+						  None		=> false
+					  })
+			},
+			(STRUCT(x), STRUCT(y))						=> {
+				let (x, y) = (x.borrow(), y.borrow());
+				break x.name == y.name && x.fields.len() == y.fields.len() &&
+					  x.fields.iter().zip(y.fields.iter()).all(|(x, y)|
+						  x.0 == y.0 && equal_walk(&x.1, &y.1, path_a, path_b))
+			},
+			_											=> break false
+		};
+		a = next.0;
+		b = next.1;
+	};
+	leave(path_a, entered_a);
+	leave(path_b, entered_b);
+	res
+}
+
+//Hashes consistently with equal_datum: a cycle hashes as the position it leads
+//back to instead of being followed again
+fn hash_datum<H: Hasher>(d: &Datum, state: &mut H, path: &mut Path) {
+	let mut entered = vec![];
+	let mut curr = d.clone();
+	loop {
+		if let Some(id) = datum_id(&curr) {
+			if let Some(depth) = path.get(&id) {
+				depth.hash(state);
+				break;
+			}
+			let depth = path.len();
+			path.insert(id, depth);
+			entered.push(id);
+		}
+		curr = match curr {
+			ATOM(NUMBER(ref n))	=> {
+				//1 and 1.0 are EQUAL?, so a number a float can hold exactly hashes
+				//by that float. -0.0 and every NaN are folded together so they find
+				//each other. Any other exact number hashes by its simplest form.
+				match exact_float(n) {
+					Some(val)	=> {
+						let val = if val == 0. {0.} else if val.is_nan() {f64::NAN} else {val};
+						val.to_bits().hash(state);
+					},
+					None		=> match n.simplify() {
+						RATIONAL(a, b)		=> (a, b).hash(state),
+						INTEGER(a)			=> a.hash(state),
+						BIGINT(a)			=> a.hash(state),
+						BIGRATIONAL(a)		=> a.hash(state),
+						REAL(a)				=> a.to_bits().hash(state)
+					}
+				}
+				break;
+			},
+			ATOM(SYMBOL(s))		=> {s.hash(state); break},
+			ATOM(STRING(ref s))	=> {s.hash(state); break},
+			ATOM(CHAR(c))		=> {c.hash(state); break},
+			ATOM(T)				=> {"T".hash(state); break},
+			LIST(NIL)			=> break,
+			LIST(ref l)			=> {
+				hash_datum(&l.car(), state, path);
+				l.cdr()
+			},
+			VECTOR(ref v)		=> {
+				for item in v.borrow().iter() {
+					hash_datum(item, state, path);
+				}
+				break;
+			},
+			HASH_TABLE(ref h)	=> {h.borrow().count().hash(state); break},
+			STRUCT(ref s)		=> {
+				for (_, value) in &s.borrow().fields {
+					hash_datum(value, state, path);
+				}
+				break;
+			},
+			FUNCTION(_)			=> {"FUNCTION".hash(state); break}
+		};
+	}
+	leave(path, entered);
+}
+
+impl Hash for HashKey {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		hash_datum(&self.0, state, &mut HashMap::new())
+	}
+}
+
+//Keeps entries in insertion order, so printing and hash-keys are predictable.
+//Removing an entry leaves a hole so the other indices stay valid, and the holes
+//are compacted away once they make up half of the table.
+#[derive(Clone, Debug, Default)]
+pub struct HashTable {
+	entries: Vec<Option<(Datum, Datum)>>,
+	index: HashMap<HashKey, usize>
+}
+
+impl HashTable {
+	pub fn new() -> HashTable {
+		HashTable::default()
+	}
+
+	pub fn get(&self, key: &Datum) -> Option<&Datum> {
+		self.index.get(&HashKey(key.clone())).and_then(|&i| self.entries[i].as_ref()).map(|entry| &entry.1)
+	}
+
+	pub fn insert(&mut self, key: Datum, value: Datum) {
+		match self.index.get(&HashKey(key.clone())) {
+			Some(&i)	=> if let Some(ref mut entry) = self.entries[i] {
+				entry.1 = value
+			},
+			None		=> {
+				self.index.insert(HashKey(key.clone()), self.entries.len());
+				self.entries.push(Some((key, value)));
+			}
+		}
+	}
+
+	pub fn remove(&mut self, key: &Datum) -> Option<Datum> {
+		let i = self.index.remove(&HashKey(key.clone()))?;
+		let value = self.entries[i].take().map(|(_, value)| value);
+		if self.index.len() * 2 <= self.entries.len() {
+			self.compact();
+		}
+		value
+	}
+
+	fn compact(&mut self) {
+		self.entries.retain(|entry| entry.is_some());
+		for (i, entry) in self.entries.iter().enumerate() {
+			if let Some((ref key, _)) = *entry {
+				self.index.insert(HashKey(key.clone()), i);
+			}
+		}
+	}
+
+	pub fn count(&self) -> usize {
+		self.index.len()
+	}
+
+	pub fn entries(&self) -> impl Iterator<Item = &(Datum, Datum)> {
+		self.entries.iter().filter_map(|entry| entry.as_ref())
+	}
+}
+
+impl PartialEq for HashTable {
+	fn eq(&self, other: &HashTable) -> bool {
+		self.count() == other.count() &&
+		self.entries().all(|(k, v)| other.get(k).is_some_and(|o| equal_datum(v, o)))
+	}
+}
+
+//An instance of a type declared with defstruct. Fields keep their declared order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Struct {
+	pub name: Symbol,
+	pub fields: Vec<(Symbol, Datum)>
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -351,52 +992,176 @@ impl List {
 pub enum Native {
 	ADD, SUB, MUL, DIV, MOD, POWI, POWR,
 	GT, GE, LT, LE, MATH_EQ,
-	LIST_FUNC, CAR, CDR, CONS_FUNC, 
+	LIST_FUNC, CAR, CDR, CONS_FUNC, SET_CAR, SET_CDR,
 	NTH, NTH_CDR, MOST,
 	LOAD,
 	FLOOR, CEIL,
 	TYPE,
 	IS_ATOM, IS_LIST, IS_CONS, IS_SYMBOL,
-	EQUAL,
+	EQUAL, EQ,
 	WRITE_TO_STRING, READ_FROM_STRING, STRING_CONCAT, PRINT, FORMAT,
+	READER_CASE,
+	IS_CHAR, CHAR_TO_INTEGER, INTEGER_TO_CHAR, CHAR_UPCASE, CHAR_DOWNCASE,
+	IS_ALPHABETIC, IS_NUMERIC, IS_WHITESPACE, IS_UPPER_CASE, IS_LOWER_CASE,
+	STRING_LENGTH, SUBSTRING, STRING_SPLIT, STRING_JOIN, STRING_INDEX, STRING_REPLACE,
+	STRING_UPCASE, STRING_DOWNCASE, STRING_TRIM,
+	STRING_TO_NUMBER, NUMBER_TO_STRING, STRING_TO_LIST, LIST_TO_STRING,
+	STRING_EQ, STRING_LT, STRING_GT, STRING_LE, STRING_GE,
+	VECTOR_FUNC, IS_VECTOR, MAKE_VECTOR, VECTOR_REF, VECTOR_SET, VECTOR_LENGTH,
+	VECTOR_TO_LIST, LIST_TO_VECTOR, VECTOR_MAP,
+	MAKE_HASH_TABLE, IS_HASH_TABLE, HASH_REF, HASH_SET, HASH_REMOVE,
+	HASH_KEYS, HASH_VALUES, HASH_COUNT, HASH_TO_LIST, HASH_FOR_EACH,
+	MAKE_STRUCT, IS_STRUCT, STRUCT_REF, STRUCT_SET,
+	ERROR, SIGNAL_FUNC,
 	NOT,
 	SET,
 	GENSYM,
 	APPLY,
 	EVAL,
+	DESCRIBE, DOCUMENTATION, HELP, APROPOS,
 	RANDINT, RANDBOOL, RANDREAL
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 #[allow(non_camel_case_types)]
 pub enum Special {
-	IF,
+	IF, COND, CASE,
+	AND, OR, WHEN, UNLESS,
 	LET, LET_STAR,
 	PROGN,
 	QUOTE, BACKQUOTE,
-	DEFINE, DEFUN, DEFMACRO,
+	DEFINE, DEFUN, DEFMACRO, DEFMACRO_HYGIENIC, DEFSTRUCT,
 	LAMBDA_FUNC, MACRO_FUNC,
-	MACROEXPAND,
+	MACROEXPAND, MACROEXPAND_ALL,
+	HANDLER_CASE, IGNORE_ERRORS, UNWIND_PROTECT,
 	TIME
 }
 
-#[derive(Clone, Debug, PartialEq)]
+//Handle to an interned symbol name. Every symbol with the same name shares one
+//handle, so symbols are compared, hashed and cloned without touching the name.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+struct SymbolTable {
+	ids: HashMap<Rc<str>, u32>,
+	names: Vec<Rc<str>>,
+	upper: Vec<Option<u32>> //each symbol's upper-case form, once it is asked for
+}
+
+thread_local! {
+	static SYMBOLS: RefCell<SymbolTable> = RefCell::new(SymbolTable{ids: HashMap::new(), names: vec![], upper: vec![]});
+}
+
+impl SymbolTable {
+	fn intern(&mut self, name: &str) -> u32 {
+		if let Some(&id) = self.ids.get(name) {
+			return id;
+		}
+		let id = self.names.len() as u32;
+		let name: Rc<str> = Rc::from(name);
+		self.names.push(name.clone());
+		self.upper.push(None);
+		self.ids.insert(name, id);
+		id
+	}
+}
+
+impl Symbol {
+	pub fn intern(name: &str) -> Symbol {
+		SYMBOLS.with(|table| Symbol(table.borrow_mut().intern(name)))
+	}
+
+	pub fn name(&self) -> Rc<str> {
+		SYMBOLS.with(|table| table.borrow().names[self.0 as usize].clone())
+	}
+
+	//The symbol with this one's name in upper case, remembered so that repeated
+	//lookups don't build and intern the name again
+	pub fn upcase(&self) -> Symbol {
+		SYMBOLS.with(|table| {
+			let mut table = table.borrow_mut();
+			if let Some(id) = table.upper[self.0 as usize] {
+				return Symbol(id);
+			}
+			let name = table.names[self.0 as usize].to_uppercase();
+			let id = table.intern(&name);
+			table.upper[self.0 as usize] = Some(id);
+			Symbol(id)
+		})
+	}
+}
+
+impl PartialEq<str> for Symbol {
+	fn eq(&self, other: &str) -> bool {
+		*self.name() == *other
+	}
+}
+
+impl<'a> PartialEq<&'a str> for Symbol {
+	fn eq(&self, other: &&'a str) -> bool {
+		*self.name() == **other
+	}
+}
+
+impl fmt::Display for Symbol {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.name())
+	}
+}
+
+impl fmt::Debug for Symbol {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{:?}", self.name())
+	}
+}
+
+pub type Frame = Rc<RefCell<HashMap<Symbol, Datum>>>;
+
+#[derive(Clone)]
 pub struct Lambda {
-	pub args: Vec<String>,
-	pub optn: Vec<(String, Datum)>,
-	pub key:  Vec<(String, Datum)>,
-	pub rest: Option<String>,
+	pub args: Vec<Symbol>,
+	pub optn: Vec<(Symbol, Datum)>,
+	pub key:  Vec<(Symbol, Datum)>,
+	pub rest: Option<Symbol>,
 	pub body: Box<Datum>,
-	pub env:  HashMap<String, Datum>
+	pub env:  Vec<Frame>,
+	pub name: Option<Symbol>, //set by defun and defmacro
+	pub doc:  Option<String>
 }
 
-fn to_string(v: Vec<(String, Datum)>) -> String {
+//A closure can be stored in a frame it captures, so captured frames are
+//compared by identity and left out of debug output to avoid infinite recursion
+impl PartialEq for Lambda {
+	fn eq(&self, other: &Lambda) -> bool {
+		self.args == other.args && self.optn == other.optn &&
+		self.key == other.key && self.rest == other.rest &&
+		self.body == other.body && self.name == other.name &&
+		self.env.len() == other.env.len() &&
+		self.env.iter().zip(&other.env).all(|(a, b)| Rc::ptr_eq(a, b))
+	}
+}
+
+impl fmt::Debug for Lambda {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("Lambda")
+		 .field("name", &self.name)
+		 .field("args", &self.args)
+		 .field("optn", &self.optn)
+		 .field("key", &self.key)
+		 .field("rest", &self.rest)
+		 .field("body", &self.body)
+		 .field("env", &format!("<{} frames>", self.env.len()))
+		 .finish()
+	}
+}
+
+fn to_string(v: Vec<(Symbol, Datum)>) -> String {
 	if v.is_empty() {
 		return "[]".to_string();
 	}
 	let mut ret = "[".to_string();
-	for i in 0..v.len()-1 {
-		ret = format!("{}({:?} {}), ", ret, v[i].0, v[i].1);
+	for (name, value) in &v[..v.len()-1] {
+		ret = format!("{}({:?} {}), ", ret, name, value);
 	}
 	ret = format!("{}({:?} {})", ret, v.last().unwrap().0, v.last().unwrap().1);
 	format!("{}]", ret)
@@ -404,7 +1169,10 @@ fn to_string(v: Vec<(String, Datum)>) -> String {
 
 impl fmt::Display for Lambda {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-		let mut s = format!("{{");
+		let mut s = match self.name {
+			Some(name)	=> format!(" {}{{", name),
+			None		=> "{".to_string()
+		};
 		if !self.args.is_empty() {
 			s = format!("{}args: {:?}, ", s, self.args.clone())
 		}
@@ -416,17 +1184,39 @@ impl fmt::Display for Lambda {
 			s = format!("{}key args: {}, ", 
 				s, to_string(self.key.clone()))
 		}
-		if self.rest != None {
-			s = format!("{}rest: {:?}, ", s, self.rest.clone().unwrap())
+		if let Some(rest) = self.rest {
+			s = format!("{}rest: {:?}, ", s, rest)
 		}
 		write!(f, "{}body: {}}}", s, self.body.clone())
 	}
 }
 
 impl Lambda {
-	pub fn contains_key(&self, name: String) -> bool {
-	    self.key.clone().into_iter().map(|keyval| keyval.0)
-	    .collect::<Vec<String>>().contains(&name)
+	pub fn contains_key(&self, name: Symbol) -> bool {
+	    self.key.iter().any(|keyval| keyval.0 == name)
+	}
+
+	//The parameters as they would be written in a lambda expression
+	pub fn lambda_list(&self) -> Datum {
+		let sym = |s: &str| ATOM(SYMBOL(Symbol::intern(s)));
+		let with_default = |&(name, ref default): &(Symbol, Datum)| match *default {
+			LIST(NIL)	=> ATOM(SYMBOL(name)),
+			_			=> LIST(List::from_vec(vec![ATOM(SYMBOL(name)), default.clone()]))
+		};
+		let mut params: Vec<Datum> = self.args.iter().map(|&a| ATOM(SYMBOL(a))).collect();
+		if !self.optn.is_empty() {
+			params.push(sym("&OPTIONAL"));
+			params.extend(self.optn.iter().map(&with_default));
+		}
+		if let Some(rest) = self.rest {
+			params.push(sym("&REST"));
+			params.push(ATOM(SYMBOL(rest)));
+		}
+		if !self.key.is_empty() {
+			params.push(sym("&KEY"));
+			params.extend(self.key.iter().map(&with_default));
+		}
+		LIST(List::from_vec(params))
 	}
 }
 
@@ -434,6 +1224,7 @@ impl Lambda {
 pub enum Function {
 	SPECIAL(Special),
 	NATIVE(Native),
+	FOREIGN(Foreign),
 	LAMBDA(Lambda),
 	MACRO(Lambda)
 }
@@ -445,8 +1236,297 @@ impl fmt::Display for Function {
 		match *self {
 			SPECIAL(s)		=> write!(f, "{:?}", s),
 			NATIVE(n)		=> write!(f, "{:?}", n),
+			FOREIGN(ref r)	=> write!(f, "Foreign {}", r.name),
 			LAMBDA(ref l)	=> write!(f, "Lambda{}", l),
 			MACRO(ref m)	=> write!(f, "Macro{}", m)
 		}
 	}
-}
\ No newline at end of file
+}
+
+pub type ForeignFn = dyn Fn(&[Datum], &mut Env) -> Result<Datum, LispError>;
+
+//A Rust function registered by a program embedding the interpreter. It gets
+//the evaluated arguments and checks them itself.
+#[derive(Clone)]
+pub struct Foreign {
+	pub name: Symbol,
+	pub func: Rc<ForeignFn>
+}
+
+impl PartialEq for Foreign {
+	fn eq(&self, other: &Foreign) -> bool {
+		Rc::ptr_eq(&self.func, &other.func)
+	}
+}
+
+impl fmt::Debug for Foreign {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "Foreign {}", self.name)
+	}
+}
+
+//Location of a token in the source it was read from
+#[derive(Clone, Debug, PartialEq)]
+pub struct Span {
+	pub file: String,
+	pub line: usize,
+	pub column: usize
+}
+
+impl fmt::Display for Span {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}:{}:{}", self.file, self.line, self.column)
+	}
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Token {
+	pub atom: Atom,
+	pub span: Span
+}
+//Where each list was read from, keyed by the identity of its first cell. The
+//Weak keeps a freed cell's address from being reused while its entry remains.
+type SpanTable = HashMap<usize, (Weak<RefCell<Datum>>, Span)>;
+
+thread_local! {
+	static SPANS: RefCell<(SpanTable, usize)> = RefCell::new((HashMap::new(), 1024));
+}
+
+impl List {
+	pub fn set_span(&self, span: Span) {
+		if let CONS(ref car, _) = *self {
+			SPANS.with(|spans| {
+				let (ref mut table, ref mut limit) = *spans.borrow_mut();
+				//drop the entries of freed lists once the table has doubled
+				if table.len() >= *limit {
+					table.retain(|_, &mut (ref cell, _)| cell.upgrade().is_some());
+					*limit = cmp::max(1024, table.len() * 2);
+				}
+				table.insert(Rc::as_ptr(car) as usize, (Rc::downgrade(car), span));
+			});
+		}
+	}
+
+	pub fn span(&self) -> Option<Span> {
+		let id = self.cell_id()?;
+		SPANS.with(|spans| match spans.borrow().0.get(&id) {
+			Some((cell, span)) if cell.upgrade().is_some() => Some(span.clone()),
+			_ => None
+		})
+	}
+}
+
+//One entry of the call stack kept for backtraces
+#[derive(Clone, Debug, PartialEq)]
+pub struct Call {
+	pub name: Datum,
+	pub args: Vec<Datum>,
+	pub form: Option<List> //the call as written, for its location
+}
+
+//Long arguments are cut short so each call fits on a line
+fn summarize(d: &Datum) -> String {
+	let printed = d.to_string();
+	if printed.chars().count() > 30 {
+		format!("{}...", printed.chars().take(27).collect::<String>())
+	} else {
+		printed
+	}
+}
+
+impl fmt::Display for Call {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let mut call = format!("({}", summarize(&self.name));
+		for arg in &self.args {
+			call = format!("{} {}", call, summarize(arg));
+		}
+		match self.form.as_ref().and_then(|form| form.span()) {
+			Some(span)	=> write!(f, "{}) at {}", call, span),
+			None		=> write!(f, "{})", call)
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use testing::*;
+
+	#[test]
+	fn dropping_a_long_list_does_not_overflow() {
+		let lst = List::from_vec((0..1000000).map(int).collect());
+		drop(lst);
+	}
+
+	#[test]
+	fn dropping_a_list_keeps_shared_tails() {
+		let lst = List::from_vec((0..5).map(int).collect());
+		let tail = lst.nth_cdr(2);
+		drop(lst);
+		assert_eq!(tail, LIST(List::from_vec(vec![int(2), int(3), int(4)])));
+	}
+
+	//(0 1 ... n-1) with the last cdr pointing back to the cell at loop_to
+	fn circular(n: i64, loop_to: usize) -> List {
+		let lst = List::from_vec((0..n).map(int).collect());
+		if let LIST(CONS(_, ref cdr)) = lst.nth_cdr(n as usize - 1) {
+			*cdr.borrow_mut() = lst.nth_cdr(loop_to);
+		}
+		lst
+	}
+
+	#[test]
+	fn circular_lists_give_each_cell_once() {
+		assert_eq!(circular(3, 0).get_items(), vec![int(0), int(1), int(2)]);
+		assert_eq!(circular(5, 3).get_items().len(), 5);
+		assert_eq!(circular(1, 0).get_items(), vec![int(0)]);
+	}
+
+	#[test]
+	fn circular_structures_compare_and_hash() {
+		let (a, b) = (LIST(circular(3, 0)), LIST(circular(3, 0)));
+		assert!(equal_datum(&a, &b));
+		assert!(!equal_datum(&a, &LIST(circular(3, 1))));
+		assert!(!equal_datum(&a, &LIST(circular(4, 0))));
+
+		let v = Datum::vector(vec![int(1), int(2)]);
+		if let VECTOR(ref items) = v {
+			items.borrow_mut()[0] = v.clone();
+		}
+		assert!(equal_datum(&v, &v));
+
+		let mut table = HashTable::new();
+		table.insert(a, int(5));
+		table.insert(v.clone(), int(6));
+		assert_eq!(table.get(&b), Some(&int(5)));
+		assert_eq!(table.get(&v), Some(&int(6)));
+	}
+	#[test]
+	fn circular_structures_compare_with_eq_operators() {
+		let (a, b) = (circular(3, 0), circular(3, 0));
+		assert!(a == b && a != circular(3, 1));
+		assert!(LIST(a.clone()) == LIST(b.clone()));
+
+		let (mut x, mut y) = (HashTable::new(), HashTable::new());
+		x.insert(int(1), LIST(a));
+		y.insert(int(1), LIST(b));
+		assert!(x == y);
+
+		let table = Datum::hash_table(HashTable::new());
+		if let HASH_TABLE(ref inner) = table {
+			inner.borrow_mut().insert(int(0), table.clone());
+		}
+		let copy = Datum::hash_table(HashTable::new());
+		if let HASH_TABLE(ref inner) = copy {
+			inner.borrow_mut().insert(int(0), copy.clone());
+		}
+		assert!(table == copy);
+	}
+	#[test]
+	fn powi_handles_huge_exponents() {
+		let huge = "100000000000000000000".parse::<BigInt>().unwrap();
+		assert_eq!(INTEGER(1).checked_powi(&huge), Some(INTEGER(1)));
+		assert_eq!(INTEGER(-1).checked_powi(&(huge.clone() + 1)), Some(INTEGER(-1)));
+		assert_eq!(INTEGER(0).checked_powi(&huge), Some(INTEGER(0)));
+		assert_eq!(INTEGER(3).checked_powi(&BigInt::from(200000000)), None);
+		assert_eq!(INTEGER(2).checked_powi(&BigInt::from(10)), Some(INTEGER(1024)));
+		assert_eq!(RATIONAL(1, 2).checked_powi(&BigInt::from(-3)), Some(INTEGER(8)));
+	}
+	#[test]
+	fn upcase_is_the_upper_case_symbol() {
+		let sym = Symbol::intern("mixedCase");
+		assert_eq!(sym.upcase(), Symbol::intern("MIXEDCASE"));
+		assert_eq!(sym.upcase(), sym.upcase());
+		assert_eq!(sym.upcase().upcase(), sym.upcase());
+	}
+	fn keys(table: &HashTable) -> Vec<Datum> {
+		table.entries().map(|(key, _)| key.clone()).collect()
+	}
+
+	#[test]
+	fn hash_table_keeps_insertion_order_across_removals() {
+		let mut table = HashTable::new();
+		for i in 0..10 {
+			table.insert(int(i), int(i * i));
+		}
+		for i in 0..8 {
+			assert_eq!(table.remove(&int(i)), Some(int(i * i)));
+		}
+		assert_eq!(table.remove(&int(0)), None);
+		table.insert(int(0), int(100));
+		table.insert(int(9), int(-1));
+		assert_eq!(keys(&table), vec![int(8), int(9), int(0)]);
+		assert_eq!(table.count(), 3);
+		assert_eq!(table.get(&int(9)), Some(&int(-1)));
+		assert_eq!(table.get(&int(0)), Some(&int(100)));
+		assert_eq!(table.get(&int(3)), None);
+	}
+
+	#[test]
+	fn hash_table_matches_numbers_by_value() {
+		let mut table = HashTable::new();
+		table.insert(int(1), int(1));
+		table.insert(ATOM(NUMBER(REAL(f64::NAN))), int(2));
+		assert_eq!(table.get(&ATOM(NUMBER(REAL(1.0)))), Some(&int(1)));
+		assert_eq!(table.get(&ATOM(NUMBER(RATIONAL(2, 2)))), Some(&int(1)));
+		assert_eq!(table.get(&ATOM(NUMBER(REAL(-f64::NAN)))), Some(&int(2)));
+		assert_eq!(table.remove(&ATOM(NUMBER(REAL(f64::NAN)))), Some(int(2)));
+		assert_eq!(table.count(), 1);
+	}
+	#[test]
+	fn hash_table_keeps_neighbouring_bignums_apart() {
+		let big = |n: &str| ATOM(NUMBER(BIGINT(n.parse().unwrap())));
+		let mut table = HashTable::new();
+		table.insert(big("18446744073709551616"), int(1));
+		table.insert(big("18446744073709551617"), int(2));
+		table.insert(ATOM(NUMBER(RATIONAL(1, 3))), int(3));
+		assert_eq!(table.count(), 3);
+		assert_eq!(table.get(&big("18446744073709551616")), Some(&int(1)));
+		assert_eq!(table.get(&big("18446744073709551617")), Some(&int(2)));
+		assert_eq!(table.get(&ATOM(NUMBER(RATIONAL(2, 6)))), Some(&int(3)));
+		assert_eq!(table.get(&ATOM(NUMBER(REAL(1. / 3.)))), None);
+		assert_eq!(table.get(&ATOM(NUMBER(REAL(18446744073709551616.)))), Some(&int(1)));
+	}
+	#[test]
+	fn structure_from_checks_fields() {
+		let point = Datum::structure_from(&[sym("POINT"), sym(":X"), int(1), sym("Y"), int(2)]).unwrap();
+		assert_eq!(point, Datum::structure(Symbol::intern("POINT"),
+			vec![(Symbol::intern("X"), int(1)), (Symbol::intern("Y"), int(2))]));
+		let odd = [sym("POINT"), sym(":X")];
+		assert_eq!(Datum::structure_from(&odd), Err(INVALID_ARG_LIST(Box::new(LIST(List::from_vec(odd.to_vec()))))));
+		assert!(Datum::structure_from(&[int(1)]).is_err());
+	}
+	#[test]
+	fn dividing_by_exact_zero_is_not_a_rational() {
+		assert_eq!(INTEGER(1) / INTEGER(0), REAL(f64::INFINITY));
+		assert_eq!(RATIONAL(-1, 2) / RATIONAL(0, 1), REAL(f64::NEG_INFINITY));
+		assert!((INTEGER(0) / BIGINT(BigInt::zero())).val().is_nan());
+		assert!(!BIGRATIONAL(BigRational::new(BigInt::from(1), BigInt::from(10).pow(400))).is_zero());
+	}
+
+	#[test]
+	fn overflow_promotes_to_arbitrary_precision() {
+		let max = INTEGER(i64::MAX);
+		assert_eq!(max.clone() + INTEGER(1), BIGINT(BigInt::from(i64::MAX) + 1));
+		assert_eq!((max.clone() + INTEGER(1)) - INTEGER(1), max);
+		assert_eq!(-INTEGER(i64::MIN), BIGINT(-BigInt::from(i64::MIN)));
+		assert_eq!(max.clone() * INTEGER(2), BIGINT(BigInt::from(i64::MAX) * 2));
+		assert_eq!(RATIONAL(1, i64::MAX) * RATIONAL(1, 2),
+				   BIGRATIONAL(BigRational::new(BigInt::from(1), BigInt::from(i64::MAX) * 2)));
+		assert_eq!(RATIONAL(1, 2) + RATIONAL(1, 2), INTEGER(1));
+		assert_eq!(RATIONAL(1, 6) + RATIONAL(1, 6), RATIONAL(1, 3));
+		assert_eq!(RATIONAL(1, 2) / RATIONAL(-1, 4), INTEGER(-2));
+		assert_eq!(INTEGER(3) / INTEGER(-6), RATIONAL(-1, 2));
+		assert_eq!(INTEGER(1) + REAL(0.5), REAL(1.5));
+	}
+	#[test]
+	fn neighbouring_bignums_are_not_equal() {
+		let a: BigInt = "18446744073709551616".parse().unwrap();
+		let b = a.clone() + 1;
+		assert!(!BIGINT(a.clone()).same_value(&BIGINT(b)));
+		assert!(BIGINT(a.clone()).same_value(&BIGRATIONAL(BigRational::from_integer(a))));
+		assert!(INTEGER(1).same_value(&RATIONAL(2, 2)));
+		assert!(INTEGER(1).same_value(&REAL(1.0)));
+		assert!(!REAL(f64::NAN).same_value(&REAL(f64::NAN)));
+	}
+}